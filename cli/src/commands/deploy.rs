@@ -0,0 +1,131 @@
+use std::process;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use colored::Colorize;
+
+use crate::command::Command;
+use crate::commands::port::PortCommand;
+use crate::context::Context;
+
+pub struct DeployCommand {
+    pub service: String,
+    pub previous_port: u16,
+    pub next_port: u16,
+    pub skip_health: bool,
+    pub drain_timeout: u64,
+    pub stop_command: Option<String>,
+}
+
+impl DeployCommand {
+    async fn current_port(&self, ctx: &Context) -> Result<u16> {
+        let config: serde_json::Value = ctx
+            .client
+            .get(ctx.api_endpoint("config"))
+            .send()
+            .await?
+            .json()
+            .await?;
+
+        config["services"]
+            .as_array()
+            .and_then(|services| {
+                services
+                    .iter()
+                    .find(|service| service["name"] == self.service.as_str())
+            })
+            .and_then(|service| service["port"].as_u64())
+            .map(|port| port as u16)
+            .ok_or_else(|| anyhow!("Service '{}' not found", self.service))
+    }
+
+    async fn drain(&self, ctx: &Context) -> Result<()> {
+        println!(
+            "{}",
+            format!(
+                "Waiting up to {}s for port {} to drain...",
+                self.drain_timeout, self.previous_port
+            )
+            .blue()
+        );
+
+        let response = ctx
+            .client
+            .get(ctx.api_endpoint(&format!("services/{}/drain", self.service)))
+            .query(&[
+                ("port", self.previous_port.to_string()),
+                ("timeout_seconds", self.drain_timeout.to_string()),
+            ])
+            .send()
+            .await?;
+
+        let result: serde_json::Value = response.json().await?;
+
+        if let Some(error) = result.get("error") {
+            println!("{}", format!("✗ {}", error).red());
+            return Err(anyhow!("Error draining port {}", self.previous_port));
+        }
+
+        if result["drained"].as_bool() != Some(true) {
+            println!(
+                "{}",
+                format!(
+                    "✗ Port {} still has {} requests and {} connections",
+                    self.previous_port,
+                    result["remaining"]["requests"],
+                    result["remaining"]["connections"]
+                )
+                .red()
+            );
+            return Err(anyhow!("Port {} did not drain", self.previous_port));
+        }
+
+        println!(
+            "{}",
+            format!("✓ Port {} drained", self.previous_port).green()
+        );
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Command for DeployCommand {
+    async fn execute(&self, ctx: &Context) -> Result<()> {
+        let current_port = self.current_port(ctx).await?;
+        if current_port != self.previous_port {
+            println!(
+                "{}",
+                format!(
+                    "✗ {} is on port {}, not {}",
+                    self.service, current_port, self.previous_port
+                )
+                .red()
+            );
+            return Err(anyhow!("Unexpected current port"));
+        }
+
+        PortCommand {
+            service: self.service.clone(),
+            port: self.next_port,
+            skip_health: self.skip_health,
+        }
+        .execute(ctx)
+        .await?;
+
+        self.drain(ctx).await?;
+
+        if let Some(stop_command) = &self.stop_command {
+            println!("{}", format!("Running: {}", stop_command).blue());
+
+            let status = process::Command::new("sh")
+                .arg("-c")
+                .arg(stop_command)
+                .status()?;
+            if !status.success() {
+                return Err(anyhow!("Stop command failed with {}", status));
+            }
+        }
+
+        Ok(())
+    }
+}
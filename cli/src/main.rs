@@ -68,6 +68,12 @@ enum Commands {
         /// Skip health check
         #[arg(short, long)]
         skip_health: bool,
+        /// Seconds to wait for the previous port to drain
+        #[arg(long, default_value = "30")]
+        drain_timeout: u64,
+        /// Shell command stopping the previous instance once it has drained
+        #[arg(long)]
+        stop_command: Option<String>,
     },
     /// Show current port for a service
     Current {
@@ -85,6 +91,7 @@ async fn main() -> Result<()> {
     let ctx = context::Context::new(cli.api_url.clone());
 
     use command::Command;
+    use commands::deploy::DeployCommand;
     use commands::port::PortCommand;
 
     match &cli.command {
@@ -131,11 +138,18 @@ async fn main() -> Result<()> {
             previous_port,
             next_port,
             skip_health,
+            drain_timeout,
+            stop_command,
         } => {
-            println!(
-                "Deploy: {} (previous: {}, next: {}, skip_health: {})",
-                service, previous_port, next_port, skip_health
-            );
+            let cmd = DeployCommand {
+                service: service.clone(),
+                previous_port: *previous_port,
+                next_port: *next_port,
+                skip_health: *skip_health,
+                drain_timeout: *drain_timeout,
+                stop_command: stop_command.clone(),
+            };
+            cmd.execute(&ctx).await?;
         }
         Commands::Current { service } => {
             println!("Current: {}", service);
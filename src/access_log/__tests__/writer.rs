@@ -0,0 +1,30 @@
+#[cfg(test)]
+mod tests {
+    use crate::access_log::writer::is_rotated;
+
+    #[test]
+    fn should_only_prune_files_rotated_by_the_writer() {
+        for name in [
+            "access.log.20250101-120000",
+            "access.log.20250101-120000-1",
+            "access.log.20250101-120000-12",
+        ] {
+            assert!(is_rotated(name, "access.log"), "{}", name);
+        }
+
+        for name in [
+            "access.log",
+            "access.log.",
+            "access.log.bak",
+            "access.log.old.gz",
+            "access.log.2025-01-01",
+            "access.log.20250101-120000.gz",
+            "access.log.20250101-120000-",
+            "access.log.20250101-1200",
+            "access.logs.20250101-120000",
+            "error.log.20250101-120000",
+        ] {
+            assert!(!is_rotated(name, "access.log"), "{}", name);
+        }
+    }
+}
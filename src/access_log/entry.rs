@@ -0,0 +1,154 @@
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, HeaderMap},
+};
+use chrono::{DateTime, Local};
+use serde_json::json;
+
+use crate::{
+    env::state::AccessLogFormat,
+    utils::{request_id::RequestId, template},
+};
+
+/// One line of the access log, filled in as the request progresses.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub time: DateTime<Local>,
+    pub client_ip: Option<String>,
+    pub host: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+    pub status: u16,
+    pub bytes: u64,
+    pub duration: Duration,
+    pub upstream: Option<String>,
+    pub route_type: &'static str,
+}
+
+impl AccessLogEntry {
+    pub fn from_request(req: &Request) -> Self {
+        let headers = req.headers();
+
+        Self {
+            time: Local::now(),
+            client_ip: req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string()),
+            host: header_value(headers, header::HOST.as_str())
+                .or_else(|| req.uri().authority().map(|a| a.to_string()))
+                .unwrap_or_default(),
+            method: req.method().to_string(),
+            path: req
+                .uri()
+                .path_and_query()
+                .map(|pq| pq.to_string())
+                .unwrap_or_else(|| "/".to_string()),
+            protocol: format!("{:?}", req.version()),
+            referer: header_value(headers, header::REFERER.as_str()),
+            user_agent: header_value(headers, header::USER_AGENT.as_str()),
+            request_id: req
+                .extensions()
+                .get::<RequestId>()
+                .map(|RequestId(id)| id.clone()),
+            status: 0,
+            bytes: 0,
+            duration: Duration::ZERO,
+            upstream: None,
+            route_type: "none",
+        }
+    }
+
+    pub fn format(&self, format: AccessLogFormat, template: Option<&str>) -> String {
+        match format {
+            AccessLogFormat::Common => self.common(),
+            AccessLogFormat::Combined => format!(
+                "{} \"{}\" \"{}\"",
+                self.common(),
+                self.referer.as_deref().unwrap_or("-"),
+                self.user_agent.as_deref().unwrap_or("-")
+            ),
+            AccessLogFormat::Json => self.json(),
+            AccessLogFormat::Custom => {
+                template::render(template.unwrap_or_default(), |name| self.variable(name))
+            }
+        }
+    }
+
+    fn common(&self) -> String {
+        format!(
+            "{} - - [{}] \"{} {} {}\" {} {}",
+            self.client_ip.as_deref().unwrap_or("-"),
+            self.time.format("%d/%b/%Y:%H:%M:%S %z"),
+            self.method,
+            self.path,
+            self.protocol,
+            self.status,
+            if self.bytes == 0 {
+                "-".to_string()
+            } else {
+                self.bytes.to_string()
+            }
+        )
+    }
+
+    fn json(&self) -> String {
+        json!({
+            "time": self.time.to_rfc3339(),
+            "client_ip": self.client_ip,
+            "host": self.host,
+            "method": self.method,
+            "path": self.path,
+            "protocol": self.protocol,
+            "status": self.status,
+            "bytes": self.bytes,
+            "duration_ms": self.duration_ms(),
+            "upstream": self.upstream,
+            "route_type": self.route_type,
+            "request_id": self.request_id,
+            "referer": self.referer,
+            "user_agent": self.user_agent,
+        })
+        .to_string()
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "time_local" => self.time.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+            "time_iso8601" => self.time.to_rfc3339(),
+            "client_ip" => self.client_ip.clone().unwrap_or_else(|| "-".to_string()),
+            "host" => self.host.clone(),
+            "method" => self.method.clone(),
+            "path" => self.path.clone(),
+            "protocol" => self.protocol.clone(),
+            "status" => self.status.to_string(),
+            "bytes" => self.bytes.to_string(),
+            "duration_ms" => format!("{:.3}", self.duration_ms()),
+            "upstream" => self.upstream.clone().unwrap_or_else(|| "-".to_string()),
+            "route_type" => self.route_type.to_string(),
+            "request_id" => self.request_id.clone().unwrap_or_else(|| "-".to_string()),
+            "referer" => self.referer.clone().unwrap_or_else(|| "-".to_string()),
+            "user_agent" => self.user_agent.clone().unwrap_or_else(|| "-".to_string()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    fn duration_ms(&self) -> f64 {
+        self.duration.as_secs_f64() * 1000.0
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
@@ -0,0 +1,135 @@
+mod __tests__;
+
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{Body, Bytes, HttpBody},
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use hyper::body::{Frame, SizeHint};
+use tokio::sync::mpsc::{error::TrySendError, Sender};
+
+use self::{entry::AccessLogEntry, writer::Message};
+use crate::{
+    env::state::{AccessLogConfig, AccessLogFormat},
+    metrics::Metrics,
+    routes::proxy::RouteInfo,
+};
+
+pub mod entry;
+mod writer;
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    template: Option<String>,
+    sender: Sender<Message>,
+    metrics: Arc<Metrics>,
+}
+
+impl AccessLog {
+    pub fn start(config: &AccessLogConfig, metrics: Arc<Metrics>) -> io::Result<Option<Arc<Self>>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        if config.format == AccessLogFormat::Custom && config.template.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "access_log.template is required for the custom format",
+            ));
+        }
+
+        Ok(Some(Arc::new(Self {
+            format: config.format,
+            template: config.template.clone(),
+            sender: writer::spawn(config)?,
+            metrics,
+        })))
+    }
+
+    fn log(&self, entry: &AccessLogEntry) {
+        let line = entry.format(self.format, self.template.as_deref());
+        if let Err(TrySendError::Full(_)) = self.sender.try_send(Message::Line(line)) {
+            self.metrics.access_log_dropped_lines_total.inc();
+        }
+    }
+}
+
+/// Logs each proxied request once its response body has been fully sent (or abandoned).
+pub async fn middleware(
+    State(access_log): State<Arc<AccessLog>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let started_at = Instant::now();
+    let mut entry = AccessLogEntry::from_request(&req);
+
+    let response = next.run(req).await;
+
+    entry.status = response.status().as_u16();
+    if let Some(route_info) = response.extensions().get::<RouteInfo>() {
+        entry.route_type = route_info.route_type;
+        entry.upstream = route_info.upstream.clone();
+    }
+
+    response.map(|body| {
+        Body::new(LoggedBody {
+            inner: body,
+            entry: Some(entry),
+            started_at,
+            access_log,
+        })
+    })
+}
+
+struct LoggedBody {
+    inner: Body,
+    entry: Option<AccessLogEntry>,
+    started_at: Instant,
+    access_log: Arc<AccessLog>,
+}
+
+impl HttpBody for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if let (Some(data), Some(entry)) = (frame.data_ref(), self.entry.as_mut()) {
+                entry.bytes += data.len() as u64;
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(mut entry) = self.entry.take() {
+            entry.duration = self.started_at.elapsed();
+            self.access_log.log(&entry);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::env::state::{AccessLogConfig, AccessLogRotation, RotationInterval};
+
+/// Lines waiting for the writer thread; more are dropped rather than piling up in memory.
+const BUFFER_LINES: usize = 16 * 1024;
+
+pub enum Message {
+    Line(String),
+    Reopen,
+}
+
+/// Starts a dedicated thread that writes access log lines, so slow disks never block requests.
+pub fn spawn(config: &AccessLogConfig) -> io::Result<Sender<Message>> {
+    let mut output = match &config.path {
+        Some(path) => Output::File(FileOutput::open(path, config.rotation.clone())?),
+        None => Output::Stdout,
+    };
+    let (sender, mut receiver) = mpsc::channel(BUFFER_LINES);
+
+    std::thread::Builder::new()
+        .name("access-log".to_string())
+        .spawn(move || {
+            while let Some(message) = receiver.blocking_recv() {
+                let result = match message {
+                    Message::Line(line) => output.write_line(&line),
+                    Message::Reopen => output.reopen(),
+                };
+
+                if let Err(e) = result {
+                    tracing::error!("Failed to write access log: {}", e);
+                }
+            }
+        })?;
+
+    #[cfg(unix)]
+    if config.path.is_some() {
+        let sender = sender.clone();
+
+        tokio::spawn(async move {
+            let Ok(mut signal) =
+                tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1())
+            else {
+                tracing::error!("Failed to install SIGUSR1 handler for the access log");
+                return;
+            };
+
+            while signal.recv().await.is_some() {
+                tracing::info!("Reopening access log");
+                if sender.send(Message::Reopen).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    Ok(sender)
+}
+
+enum Output {
+    Stdout,
+    File(FileOutput),
+}
+
+impl Output {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Output::Stdout => writeln!(io::stdout().lock(), "{}", line),
+            Output::File(file) => file.write_line(line),
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout => Ok(()),
+            Output::File(file) => file.reopen(),
+        }
+    }
+}
+
+struct FileOutput {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    rotation: Option<AccessLogRotation>,
+    period: String,
+}
+
+impl FileOutput {
+    fn open(path: &str, rotation: Option<AccessLogRotation>) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        let period = current_period(rotation.as_ref());
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            rotation,
+            period,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+
+        if self.should_rotate(len) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn should_rotate(&self, len: u64) -> bool {
+        let Some(rotation) = &self.rotation else {
+            return false;
+        };
+
+        let too_large = rotation
+            .max_size_bytes
+            .is_some_and(|max| self.size > 0 && self.size + len > max);
+
+        too_large || current_period(Some(rotation)) != self.period
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let suffix = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut rotated = suffixed(&self.path, &suffix);
+        let mut attempt = 1;
+
+        while rotated.exists() {
+            rotated = suffixed(&self.path, &format!("{}-{}", suffix, attempt));
+            attempt += 1;
+        }
+
+        fs::rename(&self.path, &rotated)?;
+        self.reopen()?;
+        self.period = current_period(self.rotation.as_ref());
+
+        if let Some(rotation) = &self.rotation {
+            self.prune(rotation.max_files)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reopens the file at `path`, e.g. after an external tool such as logrotate moved it.
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file = open_append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    fn prune(&self, max_files: usize) -> io::Result<()> {
+        let Some(file_name) = self.path.file_name().and_then(|name| name.to_str()) else {
+            return Ok(());
+        };
+        let dir = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+
+        let mut rotated = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| is_rotated(name, file_name))
+            })
+            .collect::<Vec<_>>();
+
+        rotated.sort();
+
+        let excess = rotated.len().saturating_sub(max_files);
+        for path in rotated.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `name` is a file rotated from `file_name` by `rotate`, e.g. `access.log.20250101-120000`
+/// or `access.log.20250101-120000-1`.
+pub fn is_rotated(name: &str, file_name: &str) -> bool {
+    let Some(suffix) = name
+        .strip_prefix(file_name)
+        .and_then(|rest| rest.strip_prefix('.'))
+    else {
+        return false;
+    };
+    let digits = |part: &str, len: Option<usize>| {
+        !part.is_empty()
+            && part.bytes().all(|b| b.is_ascii_digit())
+            && len.is_none_or(|len| part.len() == len)
+    };
+
+    let parts: Vec<&str> = suffix.split('-').collect();
+    match parts[..] {
+        [date, time] => digits(date, Some(8)) && digits(time, Some(6)),
+        [date, time, attempt] => {
+            digits(date, Some(8)) && digits(time, Some(6)) && digits(attempt, None)
+        }
+        _ => false,
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn current_period(rotation: Option<&AccessLogRotation>) -> String {
+    let format = match rotation.and_then(|r| r.interval) {
+        Some(RotationInterval::Hourly) => "%Y%m%d%H",
+        Some(RotationInterval::Daily) => "%Y%m%d",
+        None => "",
+    };
+
+    Local::now().format(format).to_string()
+}
@@ -1,25 +1,333 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use dotenv::dotenv;
+use axum::http::{uri::Authority, HeaderValue};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use tokio::{fs, sync::RwLock};
 
+use crate::metrics::Metrics;
+use crate::routing::{headers::Headers, RouteTable};
+use crate::server::{shutdown::Shutdown, tls::ServerTls};
+use crate::upstream::{
+    breaker::CircuitBreakers, connections::ActiveConnections, health, retry::RetryBudget,
+    timeout::Timeouts, tls::UpstreamTls, Upstream,
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     pub name: String,
     pub host: String,
     pub port: u16,
+    /// Further hosts running the service on the same port; requests rotate across all of them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<String>,
+    #[serde(default, skip_serializing_if = "Scheme::is_http")]
+    pub scheme: Scheme,
+    #[serde(default, skip_serializing_if = "Protocol::is_http1")]
+    pub protocol: Protocol,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTlsConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub health_check: Option<HealthCheckConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Overrides the global `timeouts` for this service, field by field
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeouts: Option<TimeoutConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryConfig>,
+    /// Applied before the rules of the route
+    #[serde(default, skip_serializing_if = "HeaderRules::is_empty")]
+    pub headers: HeaderRules,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_host: Option<UpstreamHost>,
+    /// Announces the client with a PROXY protocol header on every connection to the service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub previous_port: Option<u16>,
 }
 
+impl Service {
+    /// `host` followed by the additional `endpoints`.
+    pub fn hosts(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.host).chain(&self.endpoints)
+    }
+
+    pub fn addresses(&self, port: u16) -> Vec<String> {
+        self.hosts()
+            .map(|host| format!("{}:{}", host, port))
+            .collect()
+    }
+}
+
+/// Host header sent to a service: `preserve` the client's, the `service` address, or any other
+/// value verbatim. When set, the TLS server name follows it unless `tls.server_name` is given.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum UpstreamHost {
+    Preserve,
+    Service,
+    Literal(String),
+}
+
+impl TryFrom<String> for UpstreamHost {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "preserve" => Ok(UpstreamHost::Preserve),
+            "service" => Ok(UpstreamHost::Service),
+            _ if HeaderValue::from_str(&value).is_err() || value.parse::<Authority>().is_err() => {
+                Err(format!("Invalid upstream_host '{}'", value))
+            }
+            _ => Ok(UpstreamHost::Literal(value)),
+        }
+    }
+}
+
+impl From<UpstreamHost> for String {
+    fn from(value: UpstreamHost) -> Self {
+        match value {
+            UpstreamHost::Preserve => "preserve".to_string(),
+            UpstreamHost::Service => "service".to_string(),
+            UpstreamHost::Literal(value) => value,
+        }
+    }
+}
+
+/// HTTP version spoken to a service: `h2c` is HTTP/2 over plain TCP with prior knowledge, `h2`
+/// is HTTP/2 negotiated with ALPN and needs `scheme: https`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Http1,
+    H2c,
+    H2,
+}
+
+impl Protocol {
+    fn is_http1(&self) -> bool {
+        *self == Protocol::Http1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+/// Accepting the PROXY protocol on a listener behind a load balancer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyProtocolConfig {
+    /// Closes connections from trusted sources that do not start with a header
+    #[serde(default)]
+    pub required: bool,
+    /// Networks whose headers are accepted, usually the load balancers. Connections from other
+    /// sources are taken as they are.
+    pub trusted_sources: Vec<IpNet>,
+}
+
+impl ProxyProtocolConfig {
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.trusted_sources.iter().any(|net| net.contains(&ip))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scheme {
+    #[default]
+    Http,
+    Https,
+}
+
+impl Scheme {
+    fn is_http(&self) -> bool {
+        *self == Scheme::Http
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpstreamTlsConfig {
+    /// PEM bundle used to verify the upstream certificate instead of the bundled web roots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ca_file: Option<String>,
+    /// SNI and certificate name to use instead of the service host
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Route {
     pub domain: String,
+    /// Restricts the route to matching request paths; several routes may share a domain
+    #[serde(
+        default,
+        with = "serde_yaml::with::singleton_map",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub path: Option<PathMatch>,
+    /// Routes of a domain are tried by descending priority first, then by specificity
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: i32,
+    /// Further request attributes that must all match; such routes are tried before routes
+    /// with the same path and fewer conditions
+    #[serde(default, skip_serializing_if = "RouteConditions::is_empty")]
+    pub conditions: RouteConditions,
+    /// Changes the path sent to the service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rewrite: Option<RewriteConfig>,
+    #[serde(default, skip_serializing_if = "HeaderRules::is_empty")]
+    pub headers: HeaderRules,
+    /// Overrides the `upstream_host` of the service
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_host: Option<UpstreamHost>,
     #[serde(flatten)]
     pub target: RouteTarget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+fn is_zero(value: &i32) -> bool {
+    *value == 0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathMatch {
+    /// The whole path, e.g. `/health`
+    Exact(String),
+    /// A path segment prefix: `/v2` matches `/v2` and `/v2/users` but not `/v2beta`
+    Prefix(String),
+    /// `*` matches within a segment, `**` across segments, e.g. `/assets/**/*.css`
+    Glob(String),
+    Regex(String),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RouteConditions {
+    /// Request methods, any of which matches
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub methods: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<ValueCondition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub query: Vec<ValueCondition>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cookies: Vec<ValueCondition>,
+}
+
+impl RouteConditions {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        usize::from(!self.methods.is_empty())
+            + self.headers.len()
+            + self.query.len()
+            + self.cookies.len()
+    }
+}
+
+/// A named header, query parameter or cookie. Without `equals` or `regex` it only has to be
+/// present.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValueCondition {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeaderRules {
+    /// Applied to the request before it is forwarded to a service
+    #[serde(default, skip_serializing_if = "HeaderActions::is_empty")]
+    pub request: HeaderActions,
+    /// Applied to the response of every route type, error responses included
+    #[serde(default, skip_serializing_if = "HeaderActions::is_empty")]
+    pub response: HeaderActions,
+}
+
+impl HeaderRules {
+    pub fn is_empty(&self) -> bool {
+        self.request.is_empty() && self.response.is_empty()
+    }
+}
+
+/// Applied in order: `remove`, `set`, `add`. Values may use `$client_ip`, `$host`,
+/// `$request_id` and `$subdomain`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeaderActions {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+    /// Replaces existing values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Appended after existing values
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub add: BTreeMap<String, String>,
+}
+
+impl HeaderActions {
+    pub fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.set.is_empty() && self.add.is_empty()
+    }
+}
+
+/// Applied in order: `strip_prefix`, `regex`, `add_prefix`. `Location` headers in responses are
+/// mapped back through the prefix changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strip_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_prefix: Option<String>,
+    /// Replaces the first match in the path, e.g. `^/v1/(.*)` to `/api/$1`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub replacement: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientAuthConfig {
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+    /// Wildcard patterns matched against one RDN of the certificate subject, e.g. `CN=svc-*`, or
+    /// against the whole subject when they name several, e.g. `CN=svc-*, O=Corp`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_subjects: Vec<String>,
+    /// Wildcard patterns matched against the DNS, URI, e-mail and IP subject alternative names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_sans: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthMode {
+    #[default]
+    Required,
+    Optional,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,6 +343,7 @@ pub enum RouteTarget {
         #[serde(default)]
         try_files: Vec<String>,
     },
+    /// `to` may use the variables of header rules, such as `$host` and `$subdomain`
     Redirect {
         to: String,
         #[serde(default = "default_redirect_code")]
@@ -54,6 +363,31 @@ pub struct HealthCheckConfig {
     pub retry_count: u32,
     #[serde(default = "default_retry_delay")]
     pub retry_delay_seconds: u64,
+    #[serde(default, skip_serializing_if = "HealthCheckMode::is_http")]
+    pub mode: HealthCheckMode,
+    /// Service name sent in gRPC health checks; empty checks the server as a whole
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub grpc_service: String,
+    /// Limit for a single check, from connecting to the last byte of the response; `0` waits
+    /// forever
+    #[serde(default = "default_health_check_timeout")]
+    pub timeout_seconds: u64,
+}
+
+/// `grpc` calls `grpc.health.v1.Health/Check` instead of requesting `path`, and needs an
+/// HTTP/2 `protocol`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthCheckMode {
+    #[default]
+    Http,
+    Grpc,
+}
+
+impl HealthCheckMode {
+    fn is_http(&self) -> bool {
+        *self == HealthCheckMode::Http
+    }
 }
 
 fn default_path() -> String {
@@ -68,12 +402,382 @@ fn default_retry_delay() -> u64 {
     1
 }
 
+fn default_health_check_timeout() -> u64 {
+    5
+}
+
 impl Default for HealthCheckConfig {
     fn default() -> Self {
         Self {
             path: default_path(),
             retry_count: default_retry_count(),
             retry_delay_seconds: default_retry_delay(),
+            mode: HealthCheckMode::default(),
+            grpc_service: String::new(),
+            timeout_seconds: default_health_check_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// Connect errors, timeouts or 5xx responses in a row that open the breaker
+    #[serde(default = "default_consecutive_failures")]
+    pub consecutive_failures: u32,
+    /// Open the breaker when this share of the requests in a window failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_rate_percent: Option<f64>,
+    #[serde(default = "default_breaker_window")]
+    pub window_seconds: u64,
+    /// Requests needed in a window before the error rate is considered
+    #[serde(default = "default_breaker_min_requests")]
+    pub min_requests: u32,
+    /// Time spent open before a single probe request is let through
+    #[serde(default = "default_breaker_cooldown")]
+    pub cooldown_seconds: u64,
+}
+
+fn default_consecutive_failures() -> u32 {
+    5
+}
+
+fn default_breaker_window() -> u64 {
+    10
+}
+
+fn default_breaker_min_requests() -> u32 {
+    20
+}
+
+fn default_breaker_cooldown() -> u64 {
+    30
+}
+
+/// Upstream timeouts in milliseconds; `0` disables a timeout.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// TCP connect plus TLS handshake
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<u64>,
+    /// From sending the request until the response headers arrive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_byte_ms: Option<u64>,
+    /// Longest pause between two chunks of the response body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_ms: Option<u64>,
+    /// Deadline for the whole exchange, including the response body
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts per request, including the first one
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Response statuses retried for idempotent requests without a body
+    #[serde(default = "default_retry_statuses")]
+    pub retry_on_status: Vec<u16>,
+    #[serde(default = "default_backoff_base_ms")]
+    pub backoff_base_ms: u64,
+    #[serde(default = "default_backoff_max_ms")]
+    pub backoff_max_ms: u64,
+    /// Retries allowed per request on top of `budget_burst`, e.g. `0.2` for 20%
+    #[serde(default = "default_budget_ratio")]
+    pub budget_ratio: f64,
+    /// Retries available up front, at least 1
+    #[serde(default = "default_budget_burst")]
+    pub budget_burst: u32,
+}
+
+fn default_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_statuses() -> Vec<u16> {
+    vec![502, 503, 504]
+}
+
+fn default_backoff_base_ms() -> u64 {
+    25
+}
+
+fn default_backoff_max_ms() -> u64 {
+    1000
+}
+
+fn default_budget_ratio() -> f64 {
+    0.2
+}
+
+fn default_budget_burst() -> u32 {
+    10
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub port: u16,
+    pub cert: String,
+    pub key: String,
+    /// PEM bundle of the CA that signs client certificates, required by routes with `client_auth`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<String>,
+    /// Also serve HTTP/3 over QUIC
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub http3: Option<Http3Config>,
+}
+
+/// HTTP/3 over QUIC with the certificates of the TLS listener. Read at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Http3Config {
+    /// UDP port, the TLS port by default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// How long clients may remember the `Alt-Svc` advertisement
+    #[serde(default = "default_alt_svc_max_age")]
+    pub alt_svc_max_age_seconds: u64,
+}
+
+fn default_alt_svc_max_age() -> u64 {
+    86400
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessLogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+    /// Line template for the `custom` format, e.g. `$client_ip "$method $path" $status`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// File to append to instead of stdout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<AccessLogRotation>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    Common,
+    #[default]
+    Combined,
+    Json,
+    Custom,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessLogRotation {
+    /// Rotate once the file grows beyond this many bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size_bytes: Option<u64>,
+    /// Rotate when the hour or day changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<RotationInterval>,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+}
+
+fn default_max_files() -> usize {
+    7
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationInterval {
+    Hourly,
+    Daily,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestIdConfig {
+    #[serde(default = "default_request_id_header")]
+    pub header: String,
+    /// Networks whose incoming request ID is reused instead of generating a new one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_clients: Vec<IpNet>,
+}
+
+fn default_request_id_header() -> String {
+    "x-request-id".to_string()
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header: default_request_id_header(),
+            trusted_clients: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtlpConfig {
+    /// Collector URL, e.g. `http://localhost:4317` for gRPC or `http://localhost:4318` for HTTP
+    pub endpoint: String,
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+    /// Fraction of new traces to sample; traces started upstream follow the caller's decision
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+    #[serde(default = "default_otlp_service_name")]
+    pub service_name: String,
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+fn default_otlp_service_name() -> String {
+    "traffic-switcher".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    #[default]
+    Grpc,
+    Http,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownConfig {
+    /// How long in-flight requests may take to finish before their connections are closed
+    #[serde(default = "default_grace_period")]
+    pub grace_period_seconds: u64,
+}
+
+fn default_grace_period() -> u64 {
+    30
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            grace_period_seconds: default_grace_period(),
+        }
+    }
+}
+
+/// A raw TCP listener. Each connection goes to the current port of `service`, or of the service
+/// routed for the SNI with `tls_passthrough`, so a port switch applies to new connections while
+/// open ones carry on. Read at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcpListenerConfig {
+    /// Identifies the listener in logs and metrics
+    pub name: String,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Picks the service from the routes by the server name in the TLS ClientHello, forwarding
+    /// the connection still encrypted
+    #[serde(default)]
+    pub tls_passthrough: bool,
+    /// Closes connections without traffic in either direction for this long
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idle_timeout_seconds: Option<u64>,
+    /// Connections beyond this many open ones are closed right away
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+}
+
+/// A UDP listener. Datagrams from each client address share a session with its own upstream
+/// socket, which carries the replies back; new sessions go to the current port of `service`.
+/// Read at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UdpListenerConfig {
+    /// Identifies the listener in logs and metrics
+    pub name: String,
+    pub port: u16,
+    pub service: String,
+    /// Ends sessions without datagrams in either direction for this long
+    #[serde(default = "default_udp_session_timeout")]
+    pub session_timeout_seconds: u64,
+    /// Datagrams that would open a session beyond this many are dropped
+    #[serde(default = "default_udp_max_sessions")]
+    pub max_sessions: usize,
+}
+
+fn default_udp_session_timeout() -> u64 {
+    60
+}
+
+fn default_udp_max_sessions() -> usize {
+    10000
+}
+
+/// HTTP/2 towards clients: prior-knowledge h2c on the plain listeners and ALPN on the TLS
+/// listener. Read at startup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Http2Config {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_max_concurrent_streams")]
+    pub max_concurrent_streams: u32,
+    /// Flow control windows in bytes, hyper's defaults when unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_stream_window_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initial_connection_window_size: Option<u32>,
+    /// Sends PING frames at this interval and closes connections that do not answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_alive_interval_seconds: Option<u64>,
+    #[serde(default = "default_keep_alive_timeout")]
+    pub keep_alive_timeout_seconds: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_max_concurrent_streams() -> u32 {
+    200
+}
+
+fn default_keep_alive_timeout() -> u64 {
+    20
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent_streams: default_max_concurrent_streams(),
+            initial_stream_window_size: None,
+            initial_connection_window_size: None,
+            keep_alive_interval_seconds: None,
+            keep_alive_timeout_seconds: default_keep_alive_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpgradeConfig {
+    /// Where runtime state is written for the new process during a binary upgrade
+    #[serde(default = "default_state_file")]
+    pub state_file: String,
+    /// How long the new process may take to start serving before the upgrade is abandoned
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout_seconds: u64,
+}
+
+fn default_state_file() -> String {
+    "traffic-switcher.state.json".to_string()
+}
+
+fn default_ready_timeout() -> u64 {
+    30
+}
+
+impl Default for UpgradeConfig {
+    fn default() -> Self {
+        Self {
+            state_file: default_state_file(),
+            ready_timeout_seconds: default_ready_timeout(),
         }
     }
 }
@@ -84,44 +788,312 @@ pub struct Config {
     pub routes: Vec<Route>,
     pub api_port: u16,
     pub proxy_port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
     #[serde(default)]
     pub health_check: HealthCheckConfig,
+    /// Default circuit breaker for services without their own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+    #[serde(default)]
+    pub request_id: RequestIdConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp: Option<OtlpConfig>,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    #[serde(default)]
+    pub upgrade: UpgradeConfig,
+    #[serde(default)]
+    pub http2: Http2Config,
+    /// PROXY protocol on the proxy and TLS listeners. Read at startup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<ProxyProtocolConfig>,
+    /// Listeners that forward raw TCP streams to a service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tcp: Vec<TcpListenerConfig>,
+    /// Listeners that forward UDP datagrams to a service
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub udp: Vec<UdpListenerConfig>,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub port: u16,
     pub proxy_port: u16,
+    pub tls_port: Option<u16>,
     pub config: Arc<RwLock<Config>>,
     pub services_map: Arc<RwLock<HashMap<String, Service>>>,
-    pub routes_map: Arc<RwLock<HashMap<String, RouteTarget>>>,
+    pub route_table: Arc<RwLock<Arc<RouteTable>>>,
+    pub upstream_tls: Arc<RwLock<HashMap<String, Arc<UpstreamTls>>>>,
+    pub server_tls: Arc<RwLock<Option<Arc<ServerTls>>>>,
+    pub retry_budgets: Arc<RwLock<HashMap<String, Arc<RetryBudget>>>>,
+    pub service_headers: Arc<RwLock<HashMap<String, Arc<Headers>>>>,
+    pub next_endpoint: Arc<AtomicUsize>,
+    pub circuit_breakers: Arc<CircuitBreakers>,
+    pub active_connections: Arc<ActiveConnections>,
+    pub shutdown: Shutdown,
+    pub metrics: Arc<Metrics>,
 }
 
 impl AppState {
-    pub async fn new() -> Self {
-        dotenv().ok();
+    pub fn new(config: Config) -> Self {
+        log::info!("Config: {:?}", config);
+
+        let upstream_tls = Self::build_upstream_tls(&config.services).unwrap();
+        let server_tls = Self::build_server_tls(&config).unwrap();
+        let route_table = RouteTable::new(&config.routes).unwrap();
+        let service_headers = Self::build_service_headers(&config).unwrap();
+        Self::check_l4_listeners(&config).unwrap();
+        Self::check_proxy_protocol(&config).unwrap();
+        Self::check_retry(&config).unwrap();
+        let metrics = Arc::new(Metrics::new());
 
-        let config = Self::load_config().await.unwrap();
+        for service in &config.services {
+            metrics
+                .service_port
+                .with_label_values(&[&service.name])
+                .set(service.port.into());
+        }
 
         Self {
             port: config.api_port,
             proxy_port: config.proxy_port,
+            tls_port: config.tls.as_ref().map(|tls| tls.port),
             config: Arc::new(RwLock::new(config.clone())),
-            services_map: Arc::new(RwLock::new(
-                config
-                    .services
-                    .into_iter()
-                    .map(|s| (s.name.clone(), s))
-                    .collect(),
-            )),
-            routes_map: Arc::new(RwLock::new(
+            services_map: Arc::new(RwLock::new(Self::build_services_map(&config))),
+            route_table: Arc::new(RwLock::new(Arc::new(route_table))),
+            upstream_tls: Arc::new(RwLock::new(upstream_tls)),
+            server_tls: Arc::new(RwLock::new(server_tls)),
+            retry_budgets: Arc::new(RwLock::new(Self::build_retry_budgets(
+                &config,
+                &HashMap::new(),
+            ))),
+            service_headers: Arc::new(RwLock::new(service_headers)),
+            next_endpoint: Arc::new(AtomicUsize::new(0)),
+            circuit_breakers: Arc::new(CircuitBreakers::new(metrics.clone())),
+            active_connections: Arc::new(ActiveConnections::new(metrics.clone())),
+            shutdown: Shutdown::new(),
+            metrics,
+        }
+    }
+
+    fn build_services_map(config: &Config) -> HashMap<String, Service> {
+        config
+            .services
+            .iter()
+            .map(|s| (s.name.clone(), s.clone()))
+            .collect()
+    }
+
+    fn build_service_headers(config: &Config) -> Result<HashMap<String, Arc<Headers>>, String> {
+        config
+            .services
+            .iter()
+            .filter(|s| !s.headers.is_empty())
+            .map(|s| {
+                let headers = Headers::compile(&s.headers)
+                    .map_err(|e| format!("Service '{}': {}", s.name, e))?;
+                Ok((s.name.clone(), Arc::new(headers)))
+            })
+            .collect()
+    }
+
+    fn check_l4_listeners(config: &Config) -> Result<(), String> {
+        for tcp in &config.tcp {
+            if tcp.service.is_some() == tcp.tls_passthrough {
+                return Err(format!(
+                    "TCP listener '{}' needs either a service or tls_passthrough",
+                    tcp.name
+                ));
+            }
+        }
+
+        let listeners = config
+            .tcp
+            .iter()
+            .map(|tcp| ("TCP", &tcp.name, tcp.service.as_ref()))
+            .chain(
                 config
-                    .routes
-                    .into_iter()
-                    .map(|r| (r.domain.clone(), r.target.clone()))
-                    .collect(),
+                    .udp
+                    .iter()
+                    .map(|udp| ("UDP", &udp.name, Some(&udp.service))),
+            );
+        let mut names = HashSet::new();
+
+        for (kind, name, service) in listeners {
+            if !names.insert((kind, name)) {
+                return Err(format!("{} listener '{}' is defined twice", kind, name));
+            }
+            let Some(service) = service else {
+                continue;
+            };
+            if !config.services.iter().any(|s| &s.name == service) {
+                return Err(format!(
+                    "{} listener '{}' forwards to unknown service '{}'",
+                    kind, name, service
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Rejects PROXY protocol settings that would let any client claim an address.
+    fn check_proxy_protocol(config: &Config) -> Result<(), String> {
+        let listeners = std::iter::once(("Proxy", "proxy", config.proxy_protocol.as_ref())).chain(
+            config
+                .tcp
+                .iter()
+                .map(|tcp| ("TCP", tcp.name.as_str(), tcp.proxy_protocol.as_ref())),
+        );
+
+        for (kind, name, proxy_protocol) in listeners {
+            if proxy_protocol.is_some_and(|p| p.trusted_sources.is_empty()) {
+                return Err(format!(
+                    "{} listener '{}' accepts the PROXY protocol without trusted_sources",
+                    kind, name
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Budgets of services whose retry settings didn't change are taken over from `current`, so
+    /// a reload doesn't refill them.
+    pub fn build_retry_budgets(
+        config: &Config,
+        current: &HashMap<String, Arc<RetryBudget>>,
+    ) -> HashMap<String, Arc<RetryBudget>> {
+        config
+            .services
+            .iter()
+            .filter_map(|s| {
+                let retry = s.retry.as_ref()?;
+                let budget = match current.get(&s.name) {
+                    Some(budget) if budget.is_built_from(retry) => budget.clone(),
+                    _ => Arc::new(RetryBudget::new(retry)),
+                };
+                Some((s.name.clone(), budget))
+            })
+            .collect()
+    }
+
+    pub fn check_retry(config: &Config) -> Result<(), String> {
+        match config
+            .services
+            .iter()
+            .find(|s| s.retry.as_ref().is_some_and(|r| r.budget_burst == 0))
+        {
+            Some(service) => Err(format!(
+                "Service '{}' has a retry budget_burst of 0, which never allows a retry",
+                service.name
             )),
+            None => Ok(()),
+        }
+    }
+
+    fn build_server_tls(
+        config: &Config,
+    ) -> Result<Option<Arc<ServerTls>>, Box<dyn std::error::Error>> {
+        let Some(tls) = &config.tls else {
+            if let Some(route) = config.routes.iter().find(|r| r.client_auth.is_some()) {
+                log::warn!(
+                    "Route '{}' requests client certificates but no TLS listener is configured",
+                    route.domain
+                );
+            }
+            return Ok(None);
+        };
+
+        if tls.client_ca.is_none() {
+            if let Some(route) = config.routes.iter().find(|r| r.client_auth.is_some()) {
+                return Err(format!(
+                    "Route '{}' uses client_auth but tls.client_ca is not set",
+                    route.domain
+                )
+                .into());
+            }
         }
+
+        Ok(Some(Arc::new(ServerTls::from_config(
+            tls,
+            config.http2.enabled,
+        )?)))
+    }
+
+    fn build_upstream_tls(
+        services: &[Service],
+    ) -> Result<HashMap<String, Arc<UpstreamTls>>, Box<dyn std::error::Error>> {
+        let mut upstream_tls = HashMap::new();
+
+        for service in services {
+            match (service.protocol, service.scheme) {
+                (Protocol::H2, Scheme::Http) => {
+                    return Err(format!(
+                        "Service '{}' uses protocol h2, which needs scheme https",
+                        service.name
+                    )
+                    .into());
+                }
+                (Protocol::H2c, Scheme::Https) => {
+                    return Err(format!(
+                        "Service '{}' uses protocol h2c over https, use h2 instead",
+                        service.name
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+        }
+
+        for service in services.iter().filter(|s| s.scheme == Scheme::Https) {
+            let tls = UpstreamTls::from_config(
+                &service.tls.clone().unwrap_or_default(),
+                service.protocol,
+            )
+            .map_err(|e| format!("Invalid TLS settings for service '{}': {}", service.name, e))?;
+            upstream_tls.insert(service.name.clone(), Arc::new(tls));
+        }
+
+        Ok(upstream_tls)
+    }
+
+    /// One upstream per endpoint of the service, rotated so consecutive requests start on
+    /// different endpoints. Retries walk the rest of the list.
+    pub async fn upstreams(&self, service: &Service) -> Vec<Upstream> {
+        let tls = self.upstream_tls.read().await.get(&service.name).cloned();
+        let timeouts = Timeouts::resolve(
+            &self.config.read().await.timeouts,
+            service.timeouts.as_ref(),
+        );
+
+        let mut upstreams: Vec<Upstream> = service
+            .hosts()
+            .map(|host| Upstream::new(service, host, service.port, tls.clone(), timeouts))
+            .collect();
+        let offset = self.next_endpoint.fetch_add(1, Ordering::Relaxed) % upstreams.len();
+        upstreams.rotate_left(offset);
+        upstreams
+    }
+
+    pub async fn circuit_breaker_config(&self, service: &Service) -> Option<CircuitBreakerConfig> {
+        match &service.circuit_breaker {
+            Some(circuit_breaker) => Some(circuit_breaker.clone()),
+            None => self.config.read().await.circuit_breaker.clone(),
+        }
+    }
+
+    pub async fn retry_budget(&self, service: &str) -> Option<Arc<RetryBudget>> {
+        self.retry_budgets.read().await.get(service).cloned()
+    }
+
+    pub async fn service_headers(&self, service: &str) -> Option<Arc<Headers>> {
+        self.service_headers.read().await.get(service).cloned()
     }
 
     pub async fn save_config(&self) -> Result<(), Box<dyn std::error::Error>> {
@@ -134,73 +1106,184 @@ impl AppState {
     pub async fn load_config() -> Result<Config, Box<dyn std::error::Error>> {
         let config_str = fs::read_to_string("config.yaml").await?;
         let config: Config = serde_yaml::from_str(&config_str)?;
-        log::info!("Config: {:?}", config);
         Ok(config)
     }
 
     pub async fn reload_config(&self) -> Result<Config, Box<dyn std::error::Error>> {
+        let result = self.apply_config_file().await;
+        let label = if result.is_ok() { "success" } else { "failure" };
+
+        self.metrics
+            .config_reloads_total
+            .with_label_values(&[label])
+            .inc();
+
+        result
+    }
+
+    async fn apply_config_file(&self) -> Result<Config, Box<dyn std::error::Error>> {
         let new_config = Self::load_config().await?;
+        log::info!("Config: {:?}", new_config);
+
+        let upstream_tls = Self::build_upstream_tls(&new_config.services)?;
+        let server_tls = Self::build_server_tls(&new_config)?;
+        let route_table = RouteTable::new(&new_config.routes)?;
+        let service_headers = Self::build_service_headers(&new_config)?;
+        Self::check_l4_listeners(&new_config)?;
+        Self::check_proxy_protocol(&new_config)?;
+        Self::check_retry(&new_config)?;
+
+        *self.upstream_tls.write().await = upstream_tls;
+        *self.server_tls.write().await = server_tls;
+        *self.services_map.write().await = Self::build_services_map(&new_config);
+        *self.route_table.write().await = Arc::new(route_table);
+        let retry_budgets =
+            Self::build_retry_budgets(&new_config, &*self.retry_budgets.read().await);
+        *self.retry_budgets.write().await = retry_budgets;
+        *self.service_headers.write().await = service_headers;
         *self.config.write().await = new_config.clone();
+        self.prune_circuit_breakers(&new_config);
+
+        self.metrics.service_port.reset();
+        for service in &new_config.services {
+            self.metrics
+                .service_port
+                .with_label_values(&[&service.name])
+                .set(service.port.into());
+        }
+
         Ok(new_config)
     }
 
+    fn prune_circuit_breakers(&self, config: &Config) {
+        let current = config
+            .services
+            .iter()
+            .flat_map(|service| {
+                service
+                    .addresses(service.port)
+                    .into_iter()
+                    .map(|address| (service.name.clone(), address))
+            })
+            .collect();
+        self.circuit_breakers.retain(&current);
+    }
+
     pub async fn update_service_port(
         &self,
         service_name: &str,
         new_port: u16,
         skip_health_check: bool,
     ) -> Result<u16, String> {
-        let mut config = self.config.write().await;
-        let mut services_map = self.services_map.write().await;
+        let result = self
+            .switch_service_port(service_name, new_port, skip_health_check)
+            .await;
+        let label = if result.is_ok() { "success" } else { "failure" };
 
-        let service = config
-            .services
-            .iter_mut()
-            .find(|s| s.name == service_name)
-            .ok_or_else(|| format!("Service '{}' not found", service_name))?;
+        self.metrics
+            .port_switches_total
+            .with_label_values(&[service_name, label])
+            .inc();
 
-        let old_port = service.port;
-        service.previous_port = Some(old_port);
-        service.port = new_port;
+        result
+    }
+
+    async fn switch_service_port(
+        &self,
+        service_name: &str,
+        new_port: u16,
+        skip_health_check: bool,
+    ) -> Result<u16, String> {
+        let (service, health_check) = {
+            let config = self.config.read().await;
+            let service = config
+                .services
+                .iter()
+                .find(|s| s.name == service_name)
+                .cloned()
+                .ok_or_else(|| format!("Service '{}' not found", service_name))?;
+            let health_check = service
+                .health_check
+                .clone()
+                .unwrap_or_else(|| config.health_check.clone());
+
+            (service, health_check)
+        };
 
         log::info!(
             "Updating service '{}' from port {} to {} (skip_health_check: {})",
             service_name,
-            old_port,
+            service.port,
             new_port,
             skip_health_check
         );
 
         if !skip_health_check {
-            let client = reqwest::Client::new();
+            // Every endpoint has to pass on the new port before traffic moves there
+            let mut candidate = service.clone();
+            candidate.port = new_port;
+            let upstreams = self.upstreams(&candidate).await;
 
-            let path = service.health_check.as_ref().unwrap().path.clone();
-            let url = format!("http://{}:{}{}", service.host, service.port, path);
+            for i in 0..health_check.retry_count {
+                let mut healthy = true;
+                for upstream in &upstreams {
+                    let response = health::check(upstream, &health_check).await;
 
-            let retry_count = service.health_check.as_ref().unwrap().retry_count;
-            let retry_delay = service.health_check.as_ref().unwrap().retry_delay_seconds;
+                    log::info!("Response from {}: {:?}", upstream, response);
 
-            for i in 0..retry_count {
-                let response = client.get(url.clone()).send().await;
+                    self.metrics
+                        .health_checks_total
+                        .with_label_values(&[
+                            service_name,
+                            if response.is_ok() {
+                                "success"
+                            } else {
+                                "failure"
+                            },
+                        ])
+                        .inc();
 
-                log::info!("Response: {:?}", response);
+                    healthy &= response.is_ok();
+                }
 
-                if response.is_ok() {
+                if healthy {
                     break;
                 }
 
-                tokio::time::sleep(std::time::Duration::from_secs(retry_delay)).await;
+                tokio::time::sleep(std::time::Duration::from_secs(
+                    health_check.retry_delay_seconds,
+                ))
+                .await;
 
-                if i == retry_count - 1 {
+                if i == health_check.retry_count - 1 {
                     return Err(format!("Service '{}' is not healthy", service_name));
                 }
             }
         }
 
+        let mut config = self.config.write().await;
+        let mut services_map = self.services_map.write().await;
+
+        let service = config
+            .services
+            .iter_mut()
+            .find(|s| s.name == service_name)
+            .ok_or_else(|| format!("Service '{}' not found", service_name))?;
+
+        let old_port = service.port;
+        service.previous_port = Some(old_port);
+        service.port = new_port;
+
         if let Some(map_service) = services_map.get_mut(service_name) {
             map_service.previous_port = Some(old_port);
             map_service.port = new_port;
         }
+        self.prune_circuit_breakers(&config);
+
+        self.metrics
+            .service_port
+            .with_label_values(&[service_name])
+            .set(new_port.into());
 
         Ok(old_port)
     }
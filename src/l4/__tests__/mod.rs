@@ -0,0 +1,3 @@
+mod sni;
+mod tcp;
+mod udp;
@@ -0,0 +1,77 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::{
+        crypto::ring, pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore,
+    };
+
+    use crate::l4::sni::{parse, ClientHello};
+
+    fn client_hello(server_name: &str) -> Vec<u8> {
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        let server_name = ServerName::try_from(server_name.to_string()).unwrap();
+        let mut connection = ClientConnection::new(Arc::new(config), server_name).unwrap();
+
+        let mut hello = Vec::new();
+        connection.write_tls(&mut hello).unwrap();
+        hello
+    }
+
+    #[test]
+    fn should_read_the_server_name() {
+        assert_eq!(
+            parse(&client_hello("API.example.com")),
+            ClientHello::Complete(Some("api.example.com".into()))
+        );
+    }
+
+    #[test]
+    fn should_wait_for_the_whole_client_hello() {
+        let hello = client_hello("example.com");
+
+        for len in [0, 3, 5, 40, hello.len() - 1] {
+            assert_eq!(parse(&hello[..len]), ClientHello::Incomplete);
+        }
+    }
+
+    #[test]
+    fn should_reassemble_a_client_hello_split_across_records() {
+        let hello = client_hello("example.com");
+        let (header, handshake) = hello.split_at(5);
+        let (first, second) = handshake.split_at(10);
+
+        let mut split = Vec::new();
+        for fragment in [first, second] {
+            split.extend_from_slice(&header[..3]);
+            split.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+            split.extend_from_slice(fragment);
+        }
+
+        assert_eq!(
+            parse(&split),
+            ClientHello::Complete(Some("example.com".into()))
+        );
+    }
+
+    #[test]
+    fn should_accept_a_client_hello_without_server_name() {
+        // Clients do not send SNI for IP addresses
+        assert_eq!(
+            parse(&client_hello("192.0.2.1")),
+            ClientHello::Complete(None)
+        );
+    }
+
+    #[test]
+    fn should_reject_other_protocols() {
+        assert_eq!(
+            parse(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"),
+            ClientHello::Invalid
+        );
+    }
+}
@@ -0,0 +1,140 @@
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    use crate::env::state::{AppState, Config};
+    use crate::l4::tcp;
+    use crate::server::shutdown::Shutdown;
+
+    /// Upstream echoing every read back, prefixed with `tag`.
+    async fn tagged_echo(tag: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0; 64];
+                    while let Ok(read @ 1..) = stream.read(&mut buf).await {
+                        let reply = [tag.as_bytes(), &buf[..read]].concat();
+                        if stream.write_all(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    async fn listener(port: u16, max_connections: usize) -> (AppState, SocketAddr) {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: db
+      host: 127.0.0.1
+      port: {}
+tcp:
+    - name: db
+      port: 0
+      service: db
+      max_connections: {}
+",
+            port, max_connections
+        ))
+        .unwrap();
+        let state = AppState::new(config.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(tcp::serve(
+            listener,
+            config.tcp[0].clone(),
+            state.clone(),
+            Shutdown::new(),
+        ));
+        (state, addr)
+    }
+
+    async fn exchange(stream: &mut TcpStream, message: &str) -> String {
+        stream.write_all(message.as_bytes()).await.unwrap();
+        let mut buf = [0; 64];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        String::from_utf8_lossy(&buf[..read]).into_owned()
+    }
+
+    /// Whether the proxy closed the connection without forwarding anything.
+    async fn is_closed(stream: &mut TcpStream) -> bool {
+        let _ = stream.write_all(b"ping").await;
+        let mut buf = [0; 64];
+        matches!(
+            tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf)).await,
+            Ok(Ok(0) | Err(_))
+        )
+    }
+
+    #[tokio::test]
+    async fn should_forward_new_connections_to_the_current_port() {
+        let (old, new) = (tagged_echo("old:").await, tagged_echo("new:").await);
+        let (state, addr) = listener(old, 10).await;
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(exchange(&mut first, "hello").await, "old:hello");
+
+        state.update_service_port("db", new, true).await.unwrap();
+
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(exchange(&mut second, "hello").await, "new:hello");
+        // Open connections stay on the port they were opened to
+        assert_eq!(exchange(&mut first, "again").await, "old:again");
+    }
+
+    #[tokio::test]
+    async fn should_close_connections_beyond_the_limit() {
+        let (state, addr) = listener(tagged_echo("").await, 2).await;
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(exchange(&mut first, "1").await, "1");
+        assert_eq!(exchange(&mut second, "2").await, "2");
+
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert!(is_closed(&mut third).await);
+        let rejected = state
+            .metrics
+            .tcp_connections_total
+            .with_label_values(&["db", "rejected"]);
+        assert_eq!(rejected.get(), 1);
+
+        // A closed connection frees its slot
+        drop(first);
+        let mut fourth = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                let mut stream = TcpStream::connect(addr).await.unwrap();
+                if stream.write_all(b"4").await.is_ok() {
+                    let mut buf = [0; 1];
+                    if let Ok(Ok(1)) =
+                        tokio::time::timeout(Duration::from_millis(200), stream.read(&mut buf))
+                            .await
+                    {
+                        return stream;
+                    }
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("a slot should free up once a connection closes");
+        assert_eq!(exchange(&mut fourth, "5").await, "5");
+    }
+}
@@ -0,0 +1,155 @@
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use tokio::net::UdpSocket;
+
+    use crate::env::state::{AppState, Config};
+    use crate::l4::udp;
+    use crate::server::shutdown::Shutdown;
+
+    /// Upstream on `socket` answering each datagram with the port it came from and its payload.
+    fn echo(socket: UdpSocket) {
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            while let Ok((len, from)) = socket.recv_from(&mut buf).await {
+                let reply = format!("{}:{}", from.port(), String::from_utf8_lossy(&buf[..len]));
+                let _ = socket.send_to(reply.as_bytes(), from).await;
+            }
+        });
+    }
+
+    async fn echo_upstream() -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        echo(socket);
+        port
+    }
+
+    async fn listener(port: u16, settings: &str) -> (AppState, SocketAddr) {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: dns
+      host: 127.0.0.1
+      port: {}
+udp:
+    - name: dns
+      port: 0
+      service: dns
+      {}
+",
+            port, settings
+        ))
+        .unwrap();
+        let state = AppState::new(config.clone());
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(udp::serve(
+            socket,
+            config.udp[0].clone(),
+            state.clone(),
+            Shutdown::new(),
+        ));
+        (state, addr)
+    }
+
+    async fn client(addr: SocketAddr) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket.connect(addr).await.unwrap();
+        socket
+    }
+
+    /// Sends `message` and returns the reply, split into the session's upstream port and payload.
+    async fn exchange(client: &UdpSocket, message: &str) -> Option<(u16, String)> {
+        client.send(message.as_bytes()).await.unwrap();
+        let mut buf = [0; 64];
+        let len = tokio::time::timeout(Duration::from_millis(500), client.recv(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        let reply = String::from_utf8_lossy(&buf[..len]).into_owned();
+        let (port, payload) = reply.split_once(':').unwrap();
+        Some((port.parse().unwrap(), payload.to_string()))
+    }
+
+    fn sessions_opened(state: &AppState) -> u64 {
+        state
+            .metrics
+            .udp_sessions_total
+            .with_label_values(&["dns"])
+            .get()
+    }
+
+    #[tokio::test]
+    async fn should_route_replies_to_the_session_of_each_client() {
+        let (state, addr) = listener(echo_upstream().await, "").await;
+        let (first, second) = (client(addr).await, client(addr).await);
+
+        let (first_port, reply) = exchange(&first, "a").await.unwrap();
+        assert_eq!(reply, "a");
+        let (second_port, reply) = exchange(&second, "b").await.unwrap();
+        assert_eq!(reply, "b");
+        assert_ne!(first_port, second_port);
+
+        // Later datagrams reuse the session
+        assert_eq!(exchange(&first, "c").await, Some((first_port, "c".into())));
+        assert_eq!(
+            exchange(&second, "d").await,
+            Some((second_port, "d".into()))
+        );
+        assert_eq!(sessions_opened(&state), 2);
+    }
+
+    #[tokio::test]
+    async fn should_expire_idle_sessions() {
+        let (state, addr) = listener(echo_upstream().await, "session_timeout_seconds: 1").await;
+        let client = client(addr).await;
+
+        let (port, _) = exchange(&client, "a").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let (next_port, _) = exchange(&client, "b").await.unwrap();
+
+        assert_ne!(port, next_port);
+        assert_eq!(sessions_opened(&state), 2);
+    }
+
+    #[tokio::test]
+    async fn should_drop_datagrams_beyond_the_session_limit() {
+        let (state, addr) = listener(echo_upstream().await, "max_sessions: 1").await;
+        let (first, second) = (client(addr).await, client(addr).await);
+
+        assert!(exchange(&first, "a").await.is_some());
+        assert_eq!(exchange(&second, "b").await, None);
+
+        let dropped = state
+            .metrics
+            .udp_dropped_packets_total
+            .with_label_values(&["dns", "session_limit"]);
+        assert_eq!(dropped.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn should_keep_sessions_while_the_upstream_restarts() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap();
+        drop(upstream);
+        let (state, addr) = listener(upstream_addr.port(), "").await;
+        let client = client(addr).await;
+
+        // Refused by the kernel, which reports it on the session's socket
+        assert_eq!(exchange(&client, "lost").await, None);
+
+        echo(UdpSocket::bind(upstream_addr).await.unwrap());
+        assert_eq!(exchange(&client, "a").await.unwrap().1, "a");
+        assert_eq!(sessions_opened(&state), 1);
+        let failed = state
+            .metrics
+            .udp_dropped_packets_total
+            .with_label_values(&["dns", "send_failed"]);
+        assert_eq!(failed.get(), 0);
+    }
+}
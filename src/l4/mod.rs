@@ -0,0 +1,125 @@
+mod __tests__;
+
+use std::{
+    io,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Instant,
+};
+
+pub mod sni;
+pub mod tcp;
+pub mod udp;
+
+/// Copies bytes both ways until both sides have closed, giving up with `TimedOut` once nothing
+/// has been read from either side for `idle_timeout`. Returns the bytes sent by the client and by
+/// the upstream.
+pub async fn pipe<C, U>(
+    client: &mut C,
+    upstream: &mut U,
+    idle_timeout: Option<Duration>,
+) -> io::Result<(u64, u64)>
+where
+    C: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    U: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let activity = Activity::new();
+    let mut client = Tracked {
+        inner: client,
+        activity: &activity,
+    };
+    let mut upstream = Tracked {
+        inner: upstream,
+        activity: &activity,
+    };
+    let copy = tokio::io::copy_bidirectional(&mut client, &mut upstream);
+
+    let Some(idle_timeout) = idle_timeout else {
+        return copy.await;
+    };
+
+    tokio::select! {
+        result = copy => result,
+        _ = activity.idle_for(idle_timeout) => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, "idle timeout"))
+        }
+    }
+}
+
+/// When data was last read, in milliseconds since `start`.
+pub struct Activity {
+    start: Instant,
+    last_read: AtomicU64,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_read: AtomicU64::new(0),
+        }
+    }
+
+    pub fn touch(&self) {
+        let elapsed = self.start.elapsed().as_millis() as u64;
+        self.last_read.store(elapsed, Ordering::Relaxed);
+    }
+
+    /// Resolves once nothing has been read for `timeout`.
+    pub async fn idle_for(&self, timeout: Duration) {
+        loop {
+            let last_read =
+                self.start + Duration::from_millis(self.last_read.load(Ordering::Relaxed));
+            let deadline = last_read + timeout;
+            if Instant::now() >= deadline {
+                return;
+            }
+            tokio::time::sleep_until(deadline).await;
+        }
+    }
+}
+
+/// A stream that records reads in an `Activity`.
+struct Tracked<'a, S: ?Sized> {
+    inner: &'a mut S,
+    activity: &'a Activity,
+}
+
+impl<S: AsyncRead + Unpin + ?Sized> AsyncRead for Tracked<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = Pin::new(&mut *self.inner).poll_read(cx, buf);
+        if buf.filled().len() > filled {
+            self.activity.touch();
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin + ?Sized> AsyncWrite for Tracked<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
+}
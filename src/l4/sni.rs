@@ -0,0 +1,152 @@
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// `ContentType.handshake` of a TLS record
+const HANDSHAKE: u8 = 22;
+/// `HandshakeType.client_hello`
+const CLIENT_HELLO: u8 = 1;
+/// `ExtensionType.server_name`
+const SERVER_NAME: u16 = 0;
+/// Largest ClientHello accepted, well above what browsers send with post-quantum key shares
+const MAX_CLIENT_HELLO: usize = 64 * 1024;
+
+/// What the bytes read so far from a TLS connection tell about its ClientHello.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ClientHello {
+    /// More bytes are needed
+    Incomplete,
+    /// Not a TLS ClientHello
+    Invalid,
+    /// The server name the client asked for, if it sent one
+    Complete(Option<String>),
+}
+
+/// Reads from `stream` until the ClientHello is complete, returning the server name together
+/// with the bytes read, which still have to be forwarded.
+pub async fn read_client_hello<S: AsyncRead + Unpin>(
+    stream: &mut S,
+) -> io::Result<(Option<String>, Vec<u8>)> {
+    let mut buf = Vec::with_capacity(1024);
+
+    loop {
+        match parse(&buf) {
+            ClientHello::Complete(server_name) => return Ok((server_name, buf)),
+            ClientHello::Invalid => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a TLS ClientHello",
+                ))
+            }
+            ClientHello::Incomplete if buf.len() >= MAX_CLIENT_HELLO => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "ClientHello is too large",
+                ))
+            }
+            ClientHello::Incomplete => {
+                if stream.read_buf(&mut buf).await? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+    }
+}
+
+/// Parses the ClientHello at the start of `buf`, which may span several TLS records.
+pub fn parse(buf: &[u8]) -> ClientHello {
+    let mut records = Reader(buf);
+    let mut handshake = Vec::new();
+
+    loop {
+        let Some(header) = records.bytes(5) else {
+            return ClientHello::Incomplete;
+        };
+        if header[0] != HANDSHAKE || header[1] != 3 {
+            return ClientHello::Invalid;
+        }
+        let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+        let Some(fragment) = records.bytes(len) else {
+            return ClientHello::Incomplete;
+        };
+        handshake.extend_from_slice(fragment);
+
+        if handshake.first().is_some_and(|&kind| kind != CLIENT_HELLO) {
+            return ClientHello::Invalid;
+        }
+        if handshake.len() < 4 {
+            continue;
+        }
+
+        let len = u32::from_be_bytes([0, handshake[1], handshake[2], handshake[3]]) as usize;
+        if let Some(body) = handshake.get(4..4 + len) {
+            return match server_name(Reader(body)) {
+                Some(server_name) => ClientHello::Complete(server_name),
+                None => ClientHello::Invalid,
+            };
+        }
+    }
+}
+
+/// Finds the host name in the `server_name` extension of a ClientHello body.
+fn server_name(mut hello: Reader) -> Option<Option<String>> {
+    hello.bytes(2 + 32)?; // legacy_version, random
+    let session_id = hello.u8()? as usize;
+    hello.bytes(session_id)?;
+    let cipher_suites = hello.u16()? as usize;
+    hello.bytes(cipher_suites)?;
+    let compression_methods = hello.u8()? as usize;
+    hello.bytes(compression_methods)?;
+
+    if hello.0.is_empty() {
+        return Some(None);
+    }
+
+    let extensions = hello.u16()? as usize;
+    let mut extensions = Reader(hello.bytes(extensions)?);
+    while !extensions.0.is_empty() {
+        let extension_type = extensions.u16()?;
+        let len = extensions.u16()? as usize;
+        let data = extensions.bytes(len)?;
+        if extension_type != SERVER_NAME {
+            continue;
+        }
+
+        let mut data = Reader(data);
+        let list = data.u16()? as usize;
+        let mut names = Reader(data.bytes(list)?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let len = names.u16()? as usize;
+            let name = names.bytes(len)?;
+            if name_type == 0 {
+                let name = std::str::from_utf8(name).ok()?;
+                return Some(Some(name.to_ascii_lowercase()));
+            }
+        }
+    }
+
+    Some(None)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
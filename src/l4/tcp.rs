@@ -0,0 +1,194 @@
+use std::{io, sync::Arc, time::Duration};
+
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpStream},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+use tracing::{debug, error, warn};
+
+use super::sni::read_client_hello;
+use crate::{
+    env::state::{AppState, TcpListenerConfig},
+    proxy_protocol::{self, Addresses, PrefixedStream},
+    server::{self, shutdown::Shutdown},
+    upstream::UpstreamIo,
+};
+
+/// How long a TLS passthrough connection may take to send its ClientHello
+const CLIENT_HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Accepts connections on a raw TCP listener until the shutdown is triggered, forwarding each
+/// one to the listener's service. Open connections are left to finish within the grace period.
+pub async fn serve(
+    listener: TcpListener,
+    config: TcpListenerConfig,
+    state: AppState,
+    shutdown: Shutdown,
+) {
+    let config = Arc::new(config);
+    let limit = config
+        .max_connections
+        .map(|max_connections| Arc::new(Semaphore::new(max_connections)));
+
+    while let Some((stream, remote_addr)) = server::accept(&listener, &shutdown).await {
+        let permit = match &limit {
+            Some(limit) => match limit.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    debug!(
+                        "TCP listener '{}' is at its connection limit, closing the connection from {}",
+                        config.name, remote_addr
+                    );
+                    state
+                        .metrics
+                        .tcp_connections_total
+                        .with_label_values(&[&config.name, "rejected"])
+                        .inc();
+                    continue;
+                }
+            },
+            None => None,
+        };
+
+        let config = config.clone();
+        let state = state.clone();
+        let connection = shutdown.connection();
+
+        tokio::spawn(async move {
+            let _connection = connection;
+            let (stream, addresses) =
+                match proxy_protocol::accept(stream, config.proxy_protocol.as_ref()).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Closing the connection from {}: {}", remote_addr, e);
+                        failed(&state, &config);
+                        return;
+                    }
+                };
+
+            forward(stream, addresses, &config, &state, permit).await;
+        });
+    }
+}
+
+async fn forward(
+    mut client: PrefixedStream<TcpStream>,
+    addresses: Addresses,
+    config: &TcpListenerConfig,
+    state: &AppState,
+    _permit: Option<OwnedSemaphorePermit>,
+) {
+    let (service_name, client_hello) = match &config.service {
+        Some(service) => (service.clone(), Vec::new()),
+        None => match passthrough_service(&mut client, state).await {
+            Ok(routed) => routed,
+            Err(e) => {
+                debug!(
+                    "Cannot pass through the TLS connection from {} on listener '{}': {}",
+                    addresses.source, config.name, e
+                );
+                failed(state, config);
+                return;
+            }
+        },
+    };
+
+    // Looked up per connection, so a port switch applies to the next one
+    let Some(service) = state.services_map.read().await.get(&service_name).cloned() else {
+        warn!(
+            "Service '{}' of TCP listener '{}' does not exist",
+            service_name, config.name
+        );
+        failed(state, config);
+        return;
+    };
+
+    for upstream in state.upstreams(&service).await {
+        let connected = if config.tls_passthrough {
+            upstream
+                .connect_tcp(Some(&addresses))
+                .await
+                .map(|stream| Box::new(stream) as Box<dyn UpstreamIo>)
+        } else {
+            upstream.connect(None, Some(&addresses)).await
+        };
+        let mut stream = match connected {
+            Ok(stream) => stream,
+            Err(e) => {
+                error!("Failed to connect to {}: {}", upstream, e);
+                state
+                    .metrics
+                    .upstream_connect_errors_total
+                    .with_label_values(&[&upstream.service, &upstream.address()])
+                    .inc();
+                continue;
+            }
+        };
+
+        state
+            .metrics
+            .tcp_connections_total
+            .with_label_values(&[&config.name, "accepted"])
+            .inc();
+        let _active = state.metrics.tcp_connection(&config.name);
+        let _active_connection = state
+            .active_connections
+            .connection(&upstream.service, &upstream.address());
+
+        let idle_timeout = config.idle_timeout_seconds.map(Duration::from_secs);
+        let result = match stream.write_all(&client_hello).await {
+            Ok(()) => super::pipe(&mut client, &mut stream, idle_timeout).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((sent, received)) => debug!(
+                "TCP connection from {} to {} closed after {} bytes sent and {} received",
+                addresses.source, upstream, sent, received
+            ),
+            Err(e) => debug!(
+                "TCP connection from {} to {} closed: {}",
+                addresses.source, upstream, e
+            ),
+        }
+        return;
+    }
+
+    failed(state, config);
+}
+
+fn failed(state: &AppState, config: &TcpListenerConfig) {
+    state
+        .metrics
+        .tcp_connections_total
+        .with_label_values(&[&config.name, "failed"])
+        .inc();
+}
+
+/// Reads the ClientHello and finds the service routed for its server name, falling back to the
+/// `*` route without one. Returns the bytes read along with the service.
+async fn passthrough_service(
+    client: &mut PrefixedStream<TcpStream>,
+    state: &AppState,
+) -> io::Result<(String, Vec<u8>)> {
+    let (server_name, client_hello) =
+        tokio::time::timeout(CLIENT_HELLO_TIMEOUT, read_client_hello(client))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no ClientHello received"))??;
+    let server_name = server_name.unwrap_or_default();
+
+    let service = state
+        .route_table
+        .read()
+        .await
+        .passthrough_service(&server_name)
+        .map(String::from)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no service is routed for '{}'", server_name),
+            )
+        })?;
+
+    Ok((service, client_hello))
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::net::{lookup_host, UdpSocket};
+use tracing::{debug, error, warn};
+
+use super::Activity;
+use crate::{
+    env::state::{AppState, UdpListenerConfig},
+    server::shutdown::Shutdown,
+    upstream::Upstream,
+};
+
+/// Largest UDP payload
+const MAX_DATAGRAM: usize = 65535;
+
+/// Datagrams kept per client while its session is being opened
+const MAX_PENDING: usize = 64;
+
+/// Forwards datagrams from one client address to the upstream chosen when its first datagram
+/// arrived.
+struct Session {
+    upstream: UdpSocket,
+    activity: Activity,
+}
+
+/// A client's session, or the datagrams waiting for it while it is being opened.
+enum Entry {
+    Opening(Vec<Vec<u8>>),
+    Open(Arc<Session>),
+}
+
+type Sessions = Mutex<HashMap<SocketAddr, Entry>>;
+
+/// Receives datagrams on a UDP listener until the shutdown is triggered, forwarding each one
+/// through the session of its client. Sessions are opened in their own task, so a slow lookup
+/// never holds up other clients.
+pub async fn serve(
+    socket: UdpSocket,
+    config: UdpListenerConfig,
+    state: AppState,
+    shutdown: Shutdown,
+) {
+    let socket = Arc::new(socket);
+    let config = Arc::new(config);
+    let sessions: Arc<Sessions> = Arc::default();
+    let mut buf = vec![0; MAX_DATAGRAM];
+
+    loop {
+        let (len, client_addr) = tokio::select! {
+            received = socket.recv_from(&mut buf) => match received {
+                Ok(received) => received,
+                Err(e) => {
+                    // ICMP errors from earlier replies surface here on some platforms
+                    debug!("UDP listener '{}' failed to receive: {}", config.name, e);
+                    continue;
+                }
+            },
+            _ = shutdown.triggered() => break,
+        };
+        let datagram = &buf[..len];
+
+        let session = {
+            let mut entries = sessions.lock().unwrap();
+            let full = entries.len() >= config.max_sessions;
+            match entries.get_mut(&client_addr) {
+                Some(Entry::Open(session)) => Some(session.clone()),
+                Some(Entry::Opening(pending)) if pending.len() < MAX_PENDING => {
+                    pending.push(datagram.to_vec());
+                    None
+                }
+                Some(Entry::Opening(_)) => {
+                    drop_packet(&state, &config, "queue_full");
+                    None
+                }
+                None if full => {
+                    drop_packet(&state, &config, "session_limit");
+                    None
+                }
+                None => {
+                    entries.insert(client_addr, Entry::Opening(vec![datagram.to_vec()]));
+                    tokio::spawn(run_session(
+                        socket.clone(),
+                        client_addr,
+                        config.clone(),
+                        state.clone(),
+                        sessions.clone(),
+                    ));
+                    None
+                }
+            }
+        };
+
+        if let Some(session) = session {
+            forward(&session, datagram, client_addr, &config, &state).await;
+        }
+    }
+}
+
+async fn forward(
+    session: &Session,
+    datagram: &[u8],
+    client_addr: SocketAddr,
+    config: &UdpListenerConfig,
+    state: &AppState,
+) {
+    session.activity.touch();
+    let mut result = session.upstream.send(datagram).await;
+    if matches!(&result, Err(e) if e.kind() == io::ErrorKind::ConnectionRefused) {
+        // Left over from an earlier datagram the upstream refused, this one was not sent
+        result = session.upstream.send(datagram).await;
+    }
+    if let Err(e) = result {
+        debug!(
+            "UDP listener '{}' failed to forward a datagram from {}: {}",
+            config.name, client_addr, e
+        );
+        drop_packet(state, config, "send_failed");
+    }
+}
+
+fn drop_packet(state: &AppState, config: &UdpListenerConfig, reason: &str) {
+    state
+        .metrics
+        .udp_dropped_packets_total
+        .with_label_values(&[&config.name, reason])
+        .inc();
+}
+
+/// Opens the session of `client_addr`, sends the datagrams that arrived meanwhile and relays
+/// replies until the session expires.
+async fn run_session(
+    socket: Arc<UdpSocket>,
+    client_addr: SocketAddr,
+    config: Arc<UdpListenerConfig>,
+    state: AppState,
+    sessions: Arc<Sessions>,
+) {
+    let Some((upstream, upstream_socket)) = open_session(&config, &state).await else {
+        if let Some(Entry::Opening(pending)) = sessions.lock().unwrap().remove(&client_addr) {
+            for _ in pending {
+                drop_packet(&state, &config, "no_upstream");
+            }
+        }
+        return;
+    };
+
+    let session = Arc::new(Session {
+        upstream: upstream_socket,
+        activity: Activity::new(),
+    });
+    let pending = match sessions
+        .lock()
+        .unwrap()
+        .insert(client_addr, Entry::Open(session.clone()))
+    {
+        Some(Entry::Opening(pending)) => pending,
+        _ => Vec::new(),
+    };
+    debug!(
+        "UDP session from {} to {} opened on listener '{}'",
+        client_addr, upstream, config.name
+    );
+
+    let _active_session = state.metrics.udp_session(&config.name);
+    let _active_connection = state
+        .active_connections
+        .connection(&upstream.service, &upstream.address());
+    for datagram in pending {
+        forward(&session, &datagram, client_addr, &config, &state).await;
+    }
+
+    let timeout = Duration::from_secs(config.session_timeout_seconds);
+    tokio::select! {
+        result = relay_replies(&session, &socket, client_addr) => {
+            if let Err(e) = result {
+                debug!("UDP session from {} ended: {}", client_addr, e);
+            }
+        }
+        _ = session.activity.idle_for(timeout) => {
+            debug!("UDP session from {} expired", client_addr);
+        }
+    }
+
+    let mut sessions = sessions.lock().unwrap();
+    if matches!(sessions.get(&client_addr), Some(Entry::Open(current)) if Arc::ptr_eq(current, &session))
+    {
+        sessions.remove(&client_addr);
+    }
+}
+
+/// Connects to the current port of the listener's service, trying its endpoints in turn.
+async fn open_session(
+    config: &UdpListenerConfig,
+    state: &AppState,
+) -> Option<(Upstream, UdpSocket)> {
+    // Looked up per session, so a port switch applies to the next one
+    let Some(service) = state
+        .services_map
+        .read()
+        .await
+        .get(&config.service)
+        .cloned()
+    else {
+        warn!(
+            "Service '{}' of UDP listener '{}' does not exist",
+            config.service, config.name
+        );
+        return None;
+    };
+
+    for upstream in state.upstreams(&service).await {
+        match connect(&upstream.address()).await {
+            Ok(upstream_socket) => return Some((upstream, upstream_socket)),
+            Err(e) => {
+                error!("Failed to open a UDP session to {}: {}", upstream, e);
+                state
+                    .metrics
+                    .upstream_connect_errors_total
+                    .with_label_values(&[&upstream.service, &upstream.address()])
+                    .inc();
+            }
+        }
+    }
+
+    None
+}
+
+/// A socket connected to `address`, so that only its replies are received.
+async fn connect(address: &str) -> io::Result<UdpSocket> {
+    let addr = lookup_host(address)
+        .await?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address"))?;
+    let local_addr = if addr.is_ipv4() {
+        SocketAddr::from(([0, 0, 0, 0], 0))
+    } else {
+        SocketAddr::from(([0u16; 8], 0))
+    };
+
+    let socket = UdpSocket::bind(local_addr).await?;
+    socket.connect(addr).await?;
+    Ok(socket)
+}
+
+/// Sends the upstream's replies to the client. The upstream refusing a datagram (an ICMP port
+/// unreachable, e.g. while it restarts) doesn't end the session. Depending on timing, the
+/// refusal is reported either here or on the next send in `forward`.
+async fn relay_replies(
+    session: &Session,
+    socket: &UdpSocket,
+    client_addr: SocketAddr,
+) -> io::Result<()> {
+    let mut buf = vec![0; MAX_DATAGRAM];
+
+    loop {
+        let len = match session.upstream.recv(&mut buf).await {
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                debug!(
+                    "Upstream of the UDP session from {} refused a datagram",
+                    client_addr
+                );
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        session.activity.touch();
+        socket.send_to(&buf[..len], client_addr).await?;
+    }
+}
@@ -1,27 +1,62 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
-use axum::Router;
+use access_log::AccessLog;
+use axum::{http::HeaderValue, middleware::from_fn_with_state, Router};
 use env::state::AppState;
 use routes::app::app;
-use tokio::signal;
+#[cfg(unix)]
+use server::upgrade::Handover;
+use server::{
+    listeners::{Inherited, Listeners},
+    shutdown::Shutdown,
+};
 use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
-use tracing::{info, Level};
-use utils::log::trace_layer_on_request;
+use tracing::{info, warn, Level};
+use utils::{
+    log::trace_layer_on_request,
+    request_id::{self, RequestIds},
+};
 
 use crate::routes::proxy::proxy_handler;
 
+mod access_log;
 mod env;
+mod l4;
+mod metrics;
+mod proxy_protocol;
 mod routes;
+mod routing;
+mod server;
+mod telemetry;
+mod upstream;
 mod utils;
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_target(false)
-        .compact()
-        .init();
+fn main() {
+    // The environment is read and changed before the runtime starts its threads
+    dotenv::dotenv().ok();
+    let inherited = Inherited::take();
+    #[cfg(unix)]
+    let handover = Handover::take();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start the runtime");
+    #[cfg(unix)]
+    runtime.block_on(run(inherited, handover));
+    #[cfg(not(unix))]
+    runtime.block_on(run(inherited));
+}
 
-    let state = AppState::new().await;
+async fn run(inherited: Inherited, #[cfg(unix)] handover: Handover) {
+    let mut config = AppState::load_config()
+        .await
+        .expect("Failed to load config");
+    let tracer_provider = telemetry::init(config.otlp.as_ref());
+    #[cfg(unix)]
+    handover.restore_state(&mut config).await;
+
+    let state = AppState::new(config);
     let api_addr = SocketAddr::from(([0, 0, 0, 0], state.port));
     let proxy_addr = SocketAddr::from(([0, 0, 0, 0], state.proxy_port));
     let api_app = app()
@@ -32,55 +67,192 @@ async fn main() {
                 .on_request(trace_layer_on_request),
         )
         .with_state(state.clone());
-    let proxy_app = Router::new().fallback(proxy_handler).with_state(state);
+    let access_log = AccessLog::start(&state.config.read().await.access_log, state.metrics.clone())
+        .expect("Failed to start access log");
+    let mut proxy_app = Router::new()
+        .fallback(proxy_handler)
+        .with_state(state.clone());
+
+    if let Some(access_log) = access_log {
+        proxy_app = proxy_app.layer(from_fn_with_state(access_log, access_log::middleware));
+    }
+
+    let request_ids = RequestIds::from_config(&state.config.read().await.request_id)
+        .expect("Invalid request ID settings");
+    let mut proxy_app = proxy_app.layer(from_fn_with_state(
+        Arc::new(request_ids),
+        request_id::middleware,
+    ));
+
+    let shutdown = state.shutdown.clone();
+    let grace_period = Duration::from_secs(state.config.read().await.shutdown.grace_period_seconds);
+    shutdown.listen_for_signals();
+
+    let tls_addr = state
+        .tls_port
+        .map(|tls_port| SocketAddr::from(([0, 0, 0, 0], tls_port)));
+    let tcp_listeners = state.config.read().await.tcp.clone();
+    let tcp_addrs: Vec<(String, SocketAddr)> = tcp_listeners
+        .iter()
+        .map(|tcp| (tcp.name.clone(), SocketAddr::from(([0, 0, 0, 0], tcp.port))))
+        .collect();
+    let udp_listeners = state.config.read().await.udp.clone();
+    let udp_addrs: Vec<(String, SocketAddr)> = udp_listeners
+        .iter()
+        .map(|udp| (udp.name.clone(), SocketAddr::from(([0, 0, 0, 0], udp.port))))
+        .collect();
+    let http3 = match (&state.config.read().await.tls, state.tls_port) {
+        (Some(tls), Some(tls_port)) => tls.http3.as_ref().map(|http3| {
+            let addr = SocketAddr::from(([0, 0, 0, 0], http3.port.unwrap_or(tls_port)));
+            let value = format!(
+                "h3=\":{}\"; ma={}",
+                addr.port(),
+                http3.alt_svc_max_age_seconds
+            );
+            (addr, HeaderValue::from_str(&value).unwrap())
+        }),
+        _ => None,
+    };
+    let mut listeners = Listeners::open(
+        inherited,
+        api_addr,
+        proxy_addr,
+        tls_addr,
+        &tcp_addrs,
+        http3.as_ref().map(|(addr, _)| *addr),
+        &udp_addrs,
+    )
+    .await
+    .expect("Failed to bind listeners");
+    #[cfg(unix)]
+    let fds = listeners.fds();
+    let http3 = match (http3, listeners.http3.take()) {
+        (Some((addr, alt_svc)), Some(socket)) => {
+            let server_tls = state.server_tls.read().await.clone().unwrap();
+            let endpoint = server::http3::endpoint(socket, &server_tls)
+                .expect("Failed to start the HTTP/3 endpoint");
+            proxy_app = proxy_app.layer(from_fn_with_state(
+                (alt_svc, state.clone()),
+                server::http3::alt_svc,
+            ));
+            Some((endpoint, addr))
+        }
+        _ => None,
+    };
+    let api_shutdown = Shutdown::new();
+    let builder = server::connection_builder(&state.config.read().await.http2);
+    let proxy_protocol = state
+        .config
+        .read()
+        .await
+        .proxy_protocol
+        .clone()
+        .map(Arc::new);
+
+    #[cfg(unix)]
+    server::upgrade::listen_for_upgrade(state.clone(), fds, api_shutdown.clone());
 
     info!("API server listening on http://{}", api_addr);
     info!("Proxy server listening on http://{}", proxy_addr);
 
-    let api_server = tokio::spawn(async move {
-        let listener = tokio::net::TcpListener::bind(api_addr)
-            .await
-            .expect("Failed to bind API server");
-        axum::serve(listener, api_app)
-            .with_graceful_shutdown(handle_shutdown())
-            .await
-            .expect("API server failed");
-    });
-    let proxy_server = tokio::spawn(async move {
-        let listener = tokio::net::TcpListener::bind(proxy_addr)
-            .await
-            .expect("Failed to bind proxy server");
-        axum::serve(listener, proxy_app)
-            .with_graceful_shutdown(handle_shutdown())
-            .await
-            .expect("Proxy server failed");
-    });
-
-    let _ = tokio::join!(api_server, proxy_server);
-}
+    // The API keeps answering during the grace period, so `/ready` can report the shutdown
+    tokio::spawn(server::serve(
+        listeners.api,
+        api_app,
+        builder.clone(),
+        None,
+        api_shutdown,
+    ));
 
-async fn handle_shutdown() {
-    let ctrl_c = async {
-        signal::ctrl_c()
-            .await
-            .expect("failed to install Ctrl+C handler");
-    };
+    let mut servers = vec![tokio::spawn(server::serve(
+        listeners.proxy,
+        proxy_app.clone(),
+        builder.clone(),
+        proxy_protocol.clone(),
+        shutdown.clone(),
+    ))];
+
+    if let (Some(tls_listener), Some(tls_addr)) = (listeners.tls, tls_addr) {
+        info!("TLS proxy server listening on https://{}", tls_addr);
+
+        servers.push(tokio::spawn(server::serve_tls(
+            tls_listener,
+            state.clone(),
+            proxy_app.clone(),
+            builder,
+            proxy_protocol,
+            shutdown.clone(),
+        )));
+    }
+
+    if let Some((endpoint, http3_addr)) = http3 {
+        info!(
+            "HTTP/3 proxy server listening on https://{} (UDP)",
+            http3_addr
+        );
+
+        servers.push(tokio::spawn(server::http3::serve(
+            endpoint,
+            state.clone(),
+            proxy_app,
+            shutdown.clone(),
+        )));
+    }
+
+    for ((_, listener), tcp) in listeners.tcp.into_iter().zip(tcp_listeners) {
+        match &tcp.service {
+            Some(service) => info!(
+                "TCP listener '{}' on port {} forwarding to service '{}'",
+                tcp.name, tcp.port, service
+            ),
+            None => info!(
+                "TCP listener '{}' on port {} passing TLS through by SNI",
+                tcp.name, tcp.port
+            ),
+        }
+
+        servers.push(tokio::spawn(l4::tcp::serve(
+            listener,
+            tcp,
+            state.clone(),
+            shutdown.clone(),
+        )));
+    }
+
+    for ((_, socket), udp) in listeners.udp.into_iter().zip(udp_listeners) {
+        info!(
+            "UDP listener '{}' on port {} forwarding to service '{}'",
+            udp.name, udp.port, udp.service
+        );
+
+        servers.push(tokio::spawn(l4::udp::serve(
+            socket,
+            udp,
+            state.clone(),
+            shutdown.clone(),
+        )));
+    }
 
     #[cfg(unix)]
-    let terminate = async {
-        signal::unix::signal(signal::unix::SignalKind::terminate())
-            .expect("failed to install signal handler")
-            .recv()
-            .await;
-    };
+    handover.notify_ready();
 
-    #[cfg(not(unix))]
-    let terminate = std::future::pending::<()>();
+    for server in servers {
+        let _ = server.await;
+    }
 
-    tokio::select! {
-        _ = ctrl_c => {},
-        _ = terminate => {},
+    info!(
+        "Stopped accepting connections, waiting up to {:?} for in-flight requests",
+        grace_period
+    );
+    let remaining = shutdown.wait_for_connections(grace_period).await;
+    if remaining > 0 {
+        warn!(
+            "Grace period expired, closing {} remaining connections",
+            remaining
+        );
     }
 
-    info!("Shutting down...");
+    if let Some(tracer_provider) = tracer_provider {
+        telemetry::shutdown(tracer_provider);
+    }
 }
@@ -0,0 +1,390 @@
+use std::time::Duration;
+
+use axum::http::{Method, StatusCode};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+/// Prometheus collectors for the proxy, exposed on the API server at `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub requests_total: IntCounterVec,
+    pub request_duration_seconds: HistogramVec,
+    pub upstream_duration_seconds: HistogramVec,
+    pub requests_in_flight: IntGauge,
+    pub upstream_connect_errors_total: IntCounterVec,
+    pub health_checks_total: IntCounterVec,
+    pub service_port: IntGaugeVec,
+    pub config_reloads_total: IntCounterVec,
+    pub port_switches_total: IntCounterVec,
+    pub upstream_retries_total: IntCounterVec,
+    pub retry_budget_exhausted_total: IntCounterVec,
+    pub circuit_breaker_state: IntGaugeVec,
+    pub circuit_breaker_opened_total: IntCounterVec,
+    pub circuit_breaker_rejections_total: IntCounterVec,
+    pub upstream_active_requests: IntGaugeVec,
+    pub upstream_active_connections: IntGaugeVec,
+    pub http3_connections_total: IntCounterVec,
+    pub http3_active_connections: IntGauge,
+    pub http3_requests_total: IntCounterVec,
+    pub tcp_connections_total: IntCounterVec,
+    pub tcp_active_connections: IntGaugeVec,
+    pub udp_sessions_total: IntCounterVec,
+    pub udp_active_sessions: IntGaugeVec,
+    pub udp_dropped_packets_total: IntCounterVec,
+    pub access_log_dropped_lines_total: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("traffic_switcher".to_string()), None)
+            .expect("Invalid metrics prefix");
+
+        let requests_total = IntCounterVec::new(
+            Opts::new("requests_total", "Proxied requests"),
+            &["route", "service", "status", "method"],
+        )
+        .unwrap();
+        let request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "request_duration_seconds",
+                "Time from receiving a request until its response headers are ready",
+            ),
+            &["route", "service"],
+        )
+        .unwrap();
+        let upstream_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "upstream_duration_seconds",
+                "Time from connecting to an upstream until its response headers arrive",
+            ),
+            &["service"],
+        )
+        .unwrap();
+        let requests_in_flight =
+            IntGauge::new("requests_in_flight", "Requests currently being handled").unwrap();
+        let upstream_connect_errors_total = IntCounterVec::new(
+            Opts::new(
+                "upstream_connect_errors_total",
+                "Failed connection attempts to upstreams",
+            ),
+            &["service", "address"],
+        )
+        .unwrap();
+        let health_checks_total = IntCounterVec::new(
+            Opts::new("health_checks_total", "Health check attempts by result"),
+            &["service", "result"],
+        )
+        .unwrap();
+        let service_port = IntGaugeVec::new(
+            Opts::new("service_port", "Port each service is currently routed to"),
+            &["service"],
+        )
+        .unwrap();
+        let config_reloads_total = IntCounterVec::new(
+            Opts::new("config_reloads_total", "Configuration reloads by result"),
+            &["result"],
+        )
+        .unwrap();
+        let port_switches_total = IntCounterVec::new(
+            Opts::new("port_switches_total", "Service port switches by result"),
+            &["service", "result"],
+        )
+        .unwrap();
+        let upstream_retries_total = IntCounterVec::new(
+            Opts::new(
+                "upstream_retries_total",
+                "Upstream request retries by reason",
+            ),
+            &["service", "reason"],
+        )
+        .unwrap();
+        let retry_budget_exhausted_total = IntCounterVec::new(
+            Opts::new(
+                "retry_budget_exhausted_total",
+                "Retries skipped because the retry budget was exhausted",
+            ),
+            &["service"],
+        )
+        .unwrap();
+        let circuit_breaker_state = IntGaugeVec::new(
+            Opts::new(
+                "circuit_breaker_state",
+                "Circuit breaker state per upstream endpoint: 0 closed, 1 open, 2 half-open",
+            ),
+            &["service", "address"],
+        )
+        .unwrap();
+        let circuit_breaker_opened_total = IntCounterVec::new(
+            Opts::new(
+                "circuit_breaker_opened_total",
+                "Times a circuit breaker opened",
+            ),
+            &["service", "address"],
+        )
+        .unwrap();
+        let circuit_breaker_rejections_total = IntCounterVec::new(
+            Opts::new(
+                "circuit_breaker_rejections_total",
+                "Requests shed because every endpoint of the service had an open circuit breaker",
+            ),
+            &["service"],
+        )
+        .unwrap();
+        let upstream_active_requests = IntGaugeVec::new(
+            Opts::new(
+                "upstream_active_requests",
+                "Requests currently being proxied per upstream address",
+            ),
+            &["service", "address"],
+        )
+        .unwrap();
+        let upstream_active_connections = IntGaugeVec::new(
+            Opts::new(
+                "upstream_active_connections",
+                "Open connections per upstream address",
+            ),
+            &["service", "address"],
+        )
+        .unwrap();
+        let http3_connections_total = IntCounterVec::new(
+            Opts::new(
+                "http3_connections_total",
+                "HTTP/3 connections by handshake result",
+            ),
+            &["result"],
+        )
+        .unwrap();
+        let http3_active_connections =
+            IntGauge::new("http3_active_connections", "Open HTTP/3 connections").unwrap();
+        let http3_requests_total = IntCounterVec::new(
+            Opts::new("http3_requests_total", "HTTP/3 request streams by result"),
+            &["result"],
+        )
+        .unwrap();
+        let tcp_connections_total = IntCounterVec::new(
+            Opts::new(
+                "tcp_connections_total",
+                "TCP listener connections by result",
+            ),
+            &["listener", "result"],
+        )
+        .unwrap();
+        let tcp_active_connections = IntGaugeVec::new(
+            Opts::new("tcp_active_connections", "Open TCP listener connections"),
+            &["listener"],
+        )
+        .unwrap();
+        let udp_sessions_total = IntCounterVec::new(
+            Opts::new("udp_sessions_total", "UDP sessions opened"),
+            &["listener"],
+        )
+        .unwrap();
+        let udp_active_sessions = IntGaugeVec::new(
+            Opts::new("udp_active_sessions", "Open UDP sessions"),
+            &["listener"],
+        )
+        .unwrap();
+        let udp_dropped_packets_total = IntCounterVec::new(
+            Opts::new(
+                "udp_dropped_packets_total",
+                "UDP datagrams dropped by reason",
+            ),
+            &["listener", "reason"],
+        )
+        .unwrap();
+        let access_log_dropped_lines_total = IntCounter::new(
+            "access_log_dropped_lines_total",
+            "Access log lines dropped because the writer fell behind",
+        )
+        .unwrap();
+
+        registry.register(Box::new(requests_total.clone())).unwrap();
+        registry
+            .register(Box::new(request_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_duration_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(requests_in_flight.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_connect_errors_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(health_checks_total.clone()))
+            .unwrap();
+        registry.register(Box::new(service_port.clone())).unwrap();
+        registry
+            .register(Box::new(config_reloads_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(port_switches_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_retries_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(retry_budget_exhausted_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(circuit_breaker_state.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(circuit_breaker_opened_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(circuit_breaker_rejections_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_active_requests.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(upstream_active_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http3_connections_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http3_active_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(http3_requests_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(tcp_connections_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(tcp_active_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(udp_sessions_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(udp_active_sessions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(udp_dropped_packets_total.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(access_log_dropped_lines_total.clone()))
+            .unwrap();
+
+        Self {
+            registry,
+            requests_total,
+            request_duration_seconds,
+            upstream_duration_seconds,
+            requests_in_flight,
+            upstream_connect_errors_total,
+            health_checks_total,
+            service_port,
+            config_reloads_total,
+            port_switches_total,
+            upstream_retries_total,
+            retry_budget_exhausted_total,
+            circuit_breaker_state,
+            circuit_breaker_opened_total,
+            circuit_breaker_rejections_total,
+            upstream_active_requests,
+            upstream_active_connections,
+            http3_connections_total,
+            http3_active_connections,
+            http3_requests_total,
+            tcp_connections_total,
+            tcp_active_connections,
+            udp_sessions_total,
+            udp_active_sessions,
+            udp_dropped_packets_total,
+            access_log_dropped_lines_total,
+        }
+    }
+
+    pub fn observe_request(
+        &self,
+        route: &str,
+        service: &str,
+        method: &Method,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        self.requests_total
+            .with_label_values(&[route, service, status_class(status), method_label(method)])
+            .inc();
+        self.request_duration_seconds
+            .with_label_values(&[route, service])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn in_flight(&self) -> InFlightGuard {
+        self.requests_in_flight.inc();
+        InFlightGuard(self.requests_in_flight.clone())
+    }
+
+    pub fn http3_connection(&self) -> InFlightGuard {
+        self.http3_active_connections.inc();
+        InFlightGuard(self.http3_active_connections.clone())
+    }
+
+    pub fn tcp_connection(&self, listener: &str) -> InFlightGuard {
+        let gauge = self.tcp_active_connections.with_label_values(&[listener]);
+        gauge.inc();
+        InFlightGuard(gauge)
+    }
+
+    pub fn udp_session(&self, listener: &str) -> InFlightGuard {
+        self.udp_sessions_total.with_label_values(&[listener]).inc();
+        let gauge = self.udp_active_sessions.with_label_values(&[listener]);
+        gauge.inc();
+        InFlightGuard(gauge)
+    }
+
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decrements the in-flight gauge when the request is done, including on early returns.
+pub struct InFlightGuard(IntGauge);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+/// Keeps label cardinality bounded when clients send arbitrary methods.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::PATCH => "PATCH",
+        Method::CONNECT => "CONNECT",
+        Method::TRACE => "TRACE",
+        _ => "OTHER",
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use crate::env::state::{ProxyProtocolConfig, ProxyProtocolVersion};
+    use crate::proxy_protocol::{encode, parse, Addresses, Header};
+
+    fn addresses(source: &str, destination: &str) -> Addresses {
+        Addresses {
+            source: source.parse::<SocketAddr>().unwrap(),
+            destination: destination.parse::<SocketAddr>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn should_parse_v1_headers() {
+        let header = b"PROXY TCP4 203.0.113.7 10.0.0.5 51234 443\r\nGET / HTTP/1.1\r\n";
+
+        assert_eq!(
+            parse(header),
+            Header::Complete {
+                len: 43,
+                addresses: Some(addresses("203.0.113.7:51234", "10.0.0.5:443")),
+            }
+        );
+        assert_eq!(
+            parse(b"PROXY UNKNOWN\r\n"),
+            Header::Complete {
+                len: 15,
+                addresses: None,
+            }
+        );
+        assert_eq!(parse(b"PROXY TCP4 203.0.113.7"), Header::Incomplete);
+        assert_eq!(parse(b"PROXY TCP4 ::1 ::1 1 2\r\n"), Header::Invalid);
+    }
+
+    #[test]
+    fn should_round_trip_v2_headers() {
+        for (source, destination) in [
+            ("203.0.113.7:51234", "10.0.0.5:443"),
+            ("[2001:db8::7]:51234", "[2001:db8::1]:443"),
+        ] {
+            let client = addresses(source, destination);
+            let mut header = encode(ProxyProtocolVersion::V2, Some(&client));
+            let len = header.len();
+            header.extend_from_slice(b"GET / HTTP/1.1\r\n");
+
+            assert_eq!(
+                parse(&header),
+                Header::Complete {
+                    len,
+                    addresses: Some(client),
+                }
+            );
+            assert_eq!(parse(&header[..len - 1]), Header::Incomplete);
+        }
+
+        let local = encode(ProxyProtocolVersion::V2, None);
+        assert_eq!(
+            parse(&local),
+            Header::Complete {
+                len: 16,
+                addresses: None,
+            }
+        );
+    }
+
+    #[test]
+    fn should_map_mixed_families_to_ipv6() {
+        let header = encode(
+            ProxyProtocolVersion::V1,
+            Some(&addresses("203.0.113.7:51234", "[2001:db8::1]:443")),
+        );
+
+        assert_eq!(
+            String::from_utf8(header).unwrap(),
+            "PROXY TCP6 ::ffff:203.0.113.7 2001:db8::1 51234 443\r\n"
+        );
+    }
+
+    #[test]
+    fn should_tell_apart_connections_without_header() {
+        assert_eq!(parse(b""), Header::Incomplete);
+        assert_eq!(parse(b"PRO"), Header::Incomplete);
+        assert_eq!(parse(b"\r\n\r\n"), Header::Incomplete);
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n"), Header::Missing);
+        assert_eq!(parse(&[0x16, 0x03, 0x01]), Header::Missing);
+    }
+
+    #[test]
+    fn should_only_trust_listed_sources() {
+        let config = |sources: &[&str]| ProxyProtocolConfig {
+            required: false,
+            trusted_sources: sources.iter().map(|s| s.parse().unwrap()).collect(),
+        };
+
+        assert!(config(&["10.0.0.0/8"]).trusts("10.1.2.3".parse().unwrap()));
+        assert!(!config(&["10.0.0.0/8"]).trusts("203.0.113.7".parse().unwrap()));
+        assert!(!config(&[]).trusts("10.1.2.3".parse().unwrap()));
+    }
+}
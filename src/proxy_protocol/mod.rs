@@ -0,0 +1,354 @@
+mod __tests__;
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+use crate::env::state::{ProxyProtocolConfig, ProxyProtocolVersion};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Longest v1 header, `PROXY TCP6` with the longest addresses and ports
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+/// How long a trusted source may take to send its header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The two ends of a client connection, as seen by the client: `source` is the client and
+/// `destination` the address it connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Addresses {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+/// What the bytes read so far from a connection tell about its PROXY protocol header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Header {
+    /// More bytes are needed
+    Incomplete,
+    /// The connection does not start with a header
+    Missing,
+    /// The header is malformed
+    Invalid,
+    /// A header of `len` bytes, with the addresses of the client unless the sender connected on
+    /// its own behalf (`LOCAL` or `UNKNOWN`)
+    Complete {
+        len: usize,
+        addresses: Option<Addresses>,
+    },
+}
+
+/// Parses a v1 or v2 header at the start of `buf`.
+pub fn parse(buf: &[u8]) -> Header {
+    if is_prefix(buf, V1_PREFIX) {
+        parse_v1(buf)
+    } else if is_prefix(buf, V2_SIGNATURE) {
+        parse_v2(buf)
+    } else {
+        Header::Missing
+    }
+}
+
+/// Whether `buf` starts with `prefix`, or could once more bytes arrive.
+fn is_prefix(buf: &[u8], prefix: &[u8]) -> bool {
+    let len = buf.len().min(prefix.len());
+    buf[..len] == prefix[..len]
+}
+
+fn parse_v1(buf: &[u8]) -> Header {
+    let Some(end) = buf.windows(2).position(|window| window == b"\r\n") else {
+        return if buf.len() < V1_MAX_LEN {
+            Header::Incomplete
+        } else {
+            Header::Invalid
+        };
+    };
+    if end + 2 > V1_MAX_LEN {
+        return Header::Invalid;
+    }
+    let Ok(line) = std::str::from_utf8(&buf[V1_PREFIX.len()..end]) else {
+        return Header::Invalid;
+    };
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    let addresses = match fields.as_slice() {
+        ["UNKNOWN", ..] => None,
+        [protocol @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let parsed = (|| {
+                let source: IpAddr = source.parse().ok()?;
+                let destination: IpAddr = destination.parse().ok()?;
+                if source.is_ipv4() != (*protocol == "TCP4")
+                    || destination.is_ipv4() != source.is_ipv4()
+                {
+                    return None;
+                }
+                Some(Addresses {
+                    source: SocketAddr::new(source, source_port.parse().ok()?),
+                    destination: SocketAddr::new(destination, destination_port.parse().ok()?),
+                })
+            })();
+            match parsed {
+                Some(addresses) => Some(addresses),
+                None => return Header::Invalid,
+            }
+        }
+        _ => return Header::Invalid,
+    };
+
+    Header::Complete {
+        len: end + 2,
+        addresses,
+    }
+}
+
+fn parse_v2(buf: &[u8]) -> Header {
+    if buf.len() < 16 {
+        return Header::Incomplete;
+    }
+    let (version, command) = (buf[12] >> 4, buf[12] & 0x0f);
+    if version != 2 || command > 1 {
+        return Header::Invalid;
+    }
+    let len = 16 + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let Some(payload) = buf.get(16..len) else {
+        return Header::Incomplete;
+    };
+
+    // LOCAL: the sender's own connection, such as a health check
+    if command == 0 {
+        return Header::Complete {
+            len,
+            addresses: None,
+        };
+    }
+
+    // TCP or UDP over IPv4 or IPv6; other families carry no usable address
+    let addresses = match buf[13] >> 4 {
+        1 if payload.len() >= 12 => {
+            let ip = |at: usize| {
+                IpAddr::V4(Ipv4Addr::new(
+                    payload[at],
+                    payload[at + 1],
+                    payload[at + 2],
+                    payload[at + 3],
+                ))
+            };
+            Some(Addresses {
+                source: SocketAddr::new(ip(0), port(payload, 8)),
+                destination: SocketAddr::new(ip(4), port(payload, 10)),
+            })
+        }
+        2 if payload.len() >= 36 => {
+            let ip = |at: usize| {
+                let octets: [u8; 16] = payload[at..at + 16].try_into().unwrap();
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            Some(Addresses {
+                source: SocketAddr::new(ip(0), port(payload, 32)),
+                destination: SocketAddr::new(ip(16), port(payload, 34)),
+            })
+        }
+        1 | 2 => return Header::Invalid,
+        _ => None,
+    };
+
+    Header::Complete { len, addresses }
+}
+
+fn port(payload: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([payload[at], payload[at + 1]])
+}
+
+/// A header announcing `addresses`, or the sender's own connection (`UNKNOWN` or `LOCAL`) when
+/// there is no client, e.g. for health checks.
+pub fn encode(version: ProxyProtocolVersion, addresses: Option<&Addresses>) -> Vec<u8> {
+    // Both ends must be of the same family, so IPv4 addresses are mapped when they are not
+    let addresses = addresses.map(|addresses| {
+        if addresses.source.is_ipv4() == addresses.destination.is_ipv4() {
+            (addresses.source, addresses.destination)
+        } else {
+            (to_ipv6(addresses.source), to_ipv6(addresses.destination))
+        }
+    });
+
+    match version {
+        ProxyProtocolVersion::V1 => match addresses {
+            Some((source, destination)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if source.is_ipv4() { "TCP4" } else { "TCP6" },
+                source.ip(),
+                destination.ip(),
+                source.port(),
+                destination.port()
+            )
+            .into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        },
+        ProxyProtocolVersion::V2 => {
+            let mut header = V2_SIGNATURE.to_vec();
+            match addresses {
+                Some((source, destination)) => {
+                    let mut payload = Vec::with_capacity(36);
+                    for addr in [source, destination] {
+                        match addr.ip() {
+                            IpAddr::V4(ip) => payload.extend_from_slice(&ip.octets()),
+                            IpAddr::V6(ip) => payload.extend_from_slice(&ip.octets()),
+                        }
+                    }
+                    payload.extend_from_slice(&source.port().to_be_bytes());
+                    payload.extend_from_slice(&destination.port().to_be_bytes());
+
+                    header.push(0x21); // version 2, PROXY
+                    header.push(if source.is_ipv4() { 0x11 } else { 0x21 }); // TCP over IPv4 or IPv6
+                    header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+                    header.extend_from_slice(&payload);
+                }
+                None => {
+                    header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]); // version 2, LOCAL
+                }
+            }
+            header
+        }
+    }
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+/// Reads the PROXY protocol header of a connection from a trusted source, returning the
+/// connection with the bytes read past the header and the addresses of the client. Connections
+/// from other sources, or without a header when it is optional, keep their own addresses.
+pub async fn accept(
+    mut stream: TcpStream,
+    config: Option<&ProxyProtocolConfig>,
+) -> io::Result<(PrefixedStream<TcpStream>, Addresses)> {
+    let peer = Addresses {
+        source: stream.peer_addr()?,
+        destination: stream.local_addr()?,
+    };
+    let Some(config) = config.filter(|config| config.trusts(peer.source.ip())) else {
+        return Ok((PrefixedStream::new(Vec::new(), stream), peer));
+    };
+
+    let mut buf = Vec::with_capacity(256);
+    loop {
+        match parse(&buf) {
+            Header::Complete { len, addresses } => {
+                let rest = buf.split_off(len);
+                return Ok((PrefixedStream::new(rest, stream), addresses.unwrap_or(peer)));
+            }
+            Header::Missing if !config.required => {
+                return Ok((PrefixedStream::new(buf, stream), peer));
+            }
+            Header::Missing => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "PROXY protocol header is missing",
+                ))
+            }
+            Header::Invalid => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "PROXY protocol header is invalid",
+                ))
+            }
+            Header::Incomplete => {
+                match tokio::time::timeout(HEADER_TIMEOUT, stream.read_buf(&mut buf)).await {
+                    Ok(Ok(0)) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => return Err(e),
+                    // The client may be waiting for the server to speak first
+                    Err(_) if !config.required && buf.is_empty() => {
+                        return Ok((PrefixedStream::new(buf, stream), peer));
+                    }
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "PROXY protocol header timed out",
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A stream that yields bytes already read from it before reading more.
+pub struct PrefixedStream<S> {
+    prefix: Vec<u8>,
+    read: usize,
+    inner: S,
+}
+
+impl<S> PrefixedStream<S> {
+    pub fn new(prefix: Vec<u8>, inner: S) -> Self {
+        Self {
+            prefix,
+            read: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PrefixedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        if this.read < this.prefix.len() {
+            let remaining = &this.prefix[this.read..];
+            let len = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..len]);
+            this.read += len;
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PrefixedStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
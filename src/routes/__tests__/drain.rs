@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{body::Body, extract::Request, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use tokio::{net::TcpListener, sync::Notify, task::JoinHandle};
+    use tower::ServiceExt;
+
+    use crate::env::state::{AppState, Config};
+    use crate::routes::{app::app, proxy::proxy_handler};
+
+    /// Upstream holding every request until `release` is notified.
+    async fn held_upstream(release: Arc<Notify>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(move || {
+            let release = release.clone();
+            async move {
+                release.notified().await;
+                "done"
+            }
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    /// Starts a request on the current port, then moves the service to another one.
+    async fn in_flight_then_switch(
+        release: Arc<Notify>,
+    ) -> (AppState, u16, JoinHandle<StatusCode>) {
+        let port = held_upstream(release).await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: api
+services:
+    - name: api
+      host: 127.0.0.1
+      port: {}
+",
+            port
+        ))
+        .unwrap();
+        let state = AppState::new(config);
+
+        let proxy = Router::new()
+            .fallback(proxy_handler)
+            .with_state(state.clone());
+        let request = tokio::spawn(async move {
+            let req = Request::get("/")
+                .header("Host", "example.com")
+                .body(Body::empty())
+                .unwrap();
+            proxy.oneshot(req).await.unwrap().status()
+        });
+
+        let old = [format!("127.0.0.1:{}", port)];
+        while state.active_connections.counts(&old).requests == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        state.update_service_port("api", 1, true).await.unwrap();
+
+        (state, port, request)
+    }
+
+    async fn drain(state: &AppState, timeout_seconds: u64) -> (StatusCode, serde_json::Value) {
+        let req = Request::get(format!(
+            "/services/api/drain?timeout_seconds={}",
+            timeout_seconds
+        ))
+        .body(Body::empty())
+        .unwrap();
+        let response = app().with_state(state.clone()).oneshot(req).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_wait_for_requests_on_the_previous_port() {
+        let release = Arc::new(Notify::new());
+        let (state, port, request) = in_flight_then_switch(release.clone()).await;
+
+        let draining = tokio::spawn(async move { drain(&state, 10).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!draining.is_finished());
+
+        release.notify_one();
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+
+        let (status, body) = draining.await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["port"], port);
+        assert_eq!(body["drained"], true);
+        assert_eq!(body["remaining"]["requests"], 0);
+    }
+
+    #[tokio::test]
+    async fn should_answer_504_when_the_drain_times_out() {
+        let release = Arc::new(Notify::new());
+        let (state, port, request) = in_flight_then_switch(release.clone()).await;
+
+        let (status, body) = drain(&state, 1).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(body["port"], port);
+        assert_eq!(body["drained"], false);
+        assert_eq!(body["remaining"]["requests"], 1);
+
+        release.notify_one();
+        assert_eq!(request.await.unwrap(), StatusCode::OK);
+    }
+}
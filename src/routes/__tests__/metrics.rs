@@ -0,0 +1,100 @@
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use crate::env::state::{AppState, Config};
+    use crate::routes::{app::app, proxy::proxy_handler};
+
+    async fn upstream() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(|| async { "ok" });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    async fn send(proxy: &Router, method: &str, host: &str, path: &str) -> StatusCode {
+        let req = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Host", host)
+            .body(Body::empty())
+            .unwrap();
+        proxy.clone().oneshot(req).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn should_export_request_metrics_with_bounded_labels() {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      path:
+          prefix: /api
+      type: service
+      service: api
+services:
+    - name: api
+      host: 127.0.0.1
+      port: {}
+",
+            upstream().await
+        ))
+        .unwrap();
+        let state = AppState::new(config);
+        let proxy = Router::new()
+            .fallback(proxy_handler)
+            .with_state(state.clone());
+
+        assert_eq!(
+            send(&proxy, "GET", "example.com", "/api/users").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&proxy, "GET", "example.com", "/api/users").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&proxy, "PROPFIND", "example.com", "/api").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            send(&proxy, "GET", "other.com", "/").await,
+            StatusCode::NOT_FOUND
+        );
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let response = app().with_state(state).oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "text/plain; version=0.0.4"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        for line in [
+            r#"traffic_switcher_requests_total{method="GET",route="example.com/api",service="api",status="2xx"} 2"#,
+            // Unknown methods share one label value
+            r#"traffic_switcher_requests_total{method="OTHER",route="example.com/api",service="api",status="2xx"} 1"#,
+            // Unrouted requests have empty route and service labels
+            r#"traffic_switcher_requests_total{method="GET",route="",service="",status="4xx"} 1"#,
+            r#"traffic_switcher_request_duration_seconds_count{route="example.com/api",service="api"} 3"#,
+            r#"traffic_switcher_upstream_duration_seconds_count{service="api"} 3"#,
+            r#"traffic_switcher_requests_in_flight 0"#,
+            r#"traffic_switcher_service_port{service="api"} "#,
+        ] {
+            assert!(
+                body.lines().any(|l| l.starts_with(line)),
+                "missing {} in\n{}",
+                line,
+                body
+            );
+        }
+    }
+}
@@ -1 +1,4 @@
+mod drain;
 mod index;
+mod metrics;
+mod proxy;
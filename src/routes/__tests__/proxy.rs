@@ -0,0 +1,315 @@
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, extract::Request, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+    use tower::ServiceExt;
+
+    use crate::env::state::{AppState, Config, Http2Config};
+    use crate::routes::proxy::{proxy_handler, proxy_request, UpstreamError};
+    use crate::server::{self, shutdown::Shutdown};
+
+    /// Upstream answering every request with the path and query it received.
+    async fn echo_upstream() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(|req: Request| async move { req.uri().to_string() });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    fn proxy(yaml: &str) -> Router {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        Router::new()
+            .fallback(proxy_handler)
+            .with_state(AppState::new(config))
+    }
+
+    async fn send(port: u16) -> Result<StatusCode, UpstreamError> {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+      timeouts:
+          first_byte_ms: 100
+",
+            port
+        ))
+        .unwrap();
+        let state = AppState::new(config.clone());
+        let upstream = state.upstreams(&config.services[0]).await.remove(0);
+        let req = Request::get("/").body(Body::empty()).unwrap();
+
+        proxy_request(&state, req, &upstream, None)
+            .await
+            .map(|response| response.status())
+    }
+
+    #[tokio::test]
+    async fn should_answer_504_when_the_upstream_does_not_respond() {
+        // Connections are accepted by the kernel, but nothing is ever read or written
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        match send(port).await {
+            Err(e @ UpstreamError::Failed(_)) => {
+                assert_eq!(e.status(), StatusCode::GATEWAY_TIMEOUT)
+            }
+            Err(e) => panic!("request should have been sent, got {}", e.status()),
+            Ok(status) => panic!("unexpected response {}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_502_when_the_connection_fails() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        match send(port).await {
+            Err(e @ UpstreamError::NotSent(..)) => assert_eq!(e.status(), StatusCode::BAD_GATEWAY),
+            Err(e) => panic!("request should not have been sent, got {}", e.status()),
+            Ok(status) => panic!("unexpected response {}", status),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_route_on_the_normalized_path_but_forward_the_original() {
+        let port = echo_upstream().await;
+        let app = proxy(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      path:
+          prefix: /admin
+      type: service
+      service: admin
+services:
+    - name: admin
+      host: 127.0.0.1
+      port: {}
+",
+            port
+        ));
+
+        let req = Request::get("//admin/./%75sers%2Flist?q=%2e")
+            .header("Host", "example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(&body[..], b"//admin/./%75sers%2Flist?q=%2e");
+    }
+
+    #[tokio::test]
+    async fn should_apply_response_header_rules_to_upstream_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let app = proxy(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: app
+      headers:
+          response:
+              set:
+                  X-Route: $host
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+      headers:
+          response:
+              set:
+                  X-Service: app
+",
+            port
+        ));
+
+        let req = Request::get("/")
+            .header("Host", "example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(response.headers()["x-route"], "example.com");
+        assert_eq!(response.headers()["x-service"], "app");
+    }
+
+    #[tokio::test]
+    async fn should_not_feed_the_breaker_with_requests_that_never_left() {
+        let port = echo_upstream().await;
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: app
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+      protocol: h2c
+      circuit_breaker:
+          consecutive_failures: 2
+",
+            port
+        ))
+        .unwrap();
+        let state = AppState::new(config.clone());
+        let breaker = config.services[0].circuit_breaker.clone().unwrap();
+        let address = format!("127.0.0.1:{}", port);
+        state
+            .circuit_breakers
+            .record("app", &address, &breaker, false);
+
+        let app = Router::new()
+            .fallback(proxy_handler)
+            .with_state(state.clone());
+        // Not a valid authority, so the request fails before it is sent
+        let req = Request::get("/")
+            .header("Host", "example.com:{")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(state.circuit_breakers.status()[0].consecutive_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn should_render_redirect_targets_like_header_values() {
+        let app = proxy(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: redirect
+      to: https://$host/from$subdomain
+      code: 308
+      headers:
+          response:
+              set:
+                  X-Subdomain: from$subdomain
+services: []
+",
+        );
+
+        let req = Request::get("/")
+            .header("Host", "example.com")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(response.headers()["location"], "https://example.com/from");
+        assert_eq!(response.headers()["x-subdomain"], "from");
+    }
+
+    #[tokio::test]
+    async fn should_bridge_upgraded_connections() {
+        // Upstream that switches to echoing bytes back once asked to upgrade
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let head = read_head(&mut stream).await.to_ascii_lowercase();
+            assert!(head.contains("upgrade: echo"), "{}", head);
+            assert!(head.contains("connection: upgrade"), "{}", head);
+            stream
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n")
+                .await
+                .unwrap();
+            let (mut reader, mut writer) = stream.split();
+            tokio::io::copy(&mut reader, &mut writer).await.unwrap();
+        });
+
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: app
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+",
+            port
+        ))
+        .unwrap();
+        let state = AppState::new(config);
+        let app = Router::new()
+            .fallback(proxy_handler)
+            .with_state(state.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(server::serve(
+            listener,
+            app,
+            server::connection_builder(&Http2Config::default()),
+            None,
+            Shutdown::new(),
+        ));
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client
+            .write_all(b"GET /ws HTTP/1.1\r\nHost: example.com\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\n")
+            .await
+            .unwrap();
+        let head = read_head(&mut client).await.to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 101"), "{}", head);
+        assert!(head.contains("upgrade: echo"), "{}", head);
+
+        client.write_all(b"ping").await.unwrap();
+        let mut echoed = [0; 4];
+        client.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+
+        let addresses = [format!("127.0.0.1:{}", port)];
+        assert_eq!(state.active_connections.counts(&addresses).requests, 1);
+        drop(client);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while state.active_connections.counts(&addresses).requests > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the tunnel should be released once the client closes");
+    }
+
+    /// Reads an HTTP/1 message head, byte by byte so nothing after it is consumed.
+    async fn read_head(stream: &mut TcpStream) -> String {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        String::from_utf8(head).unwrap()
+    }
+}
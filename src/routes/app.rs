@@ -8,7 +8,15 @@ use crate::env::state::AppState;
 pub fn app() -> Router<AppState> {
     Router::new()
         .route("/", get(super::index::get))
+        .route("/ready", get(super::ready::get))
         .route("/config", get(super::config::index::get))
         .route("/config/reload", get(super::config::reload::get))
         .route("/config/port", post(super::config::port::post))
+        .route("/metrics", get(super::metrics::get))
+        .route("/circuit-breakers", get(super::circuit_breakers::get))
+        .route(
+            "/services/:name/connections",
+            get(super::services::connections::get),
+        )
+        .route("/services/:name/drain", get(super::services::drain::get))
 }
@@ -0,0 +1,7 @@
+use axum::{extract::State, response::IntoResponse, Json};
+
+use crate::env::state::AppState;
+
+pub async fn get(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.circuit_breakers.status())
+}
@@ -1,8 +1,19 @@
 use axum::{extract::State, response::IntoResponse, Json};
+use hyper::StatusCode;
 
 use crate::env::state::AppState;
 
 pub async fn get(State(state): State<AppState>) -> impl IntoResponse {
-    let new_config = state.reload_config().await.unwrap();
-    Json(new_config)
+    match state.reload_config().await {
+        Ok(new_config) => (StatusCode::OK, Json(serde_json::json!(new_config))),
+        Err(e) => {
+            log::error!("Failed to reload config: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "error": e.to_string()
+                })),
+            )
+        }
+    }
 }
@@ -1,7 +1,11 @@
 mod __tests__;
 
 pub mod app;
+pub mod circuit_breakers;
 pub mod config;
 pub mod index;
+pub mod metrics;
 pub mod proxy;
+pub mod ready;
+pub mod services;
 pub mod static_files;
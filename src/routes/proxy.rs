@@ -1,42 +1,225 @@
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
 use axum::{
-    extract::{Host, Request, State},
-    http::{uri::Uri, StatusCode},
+    body::{Body, HttpBody},
+    extract::{ConnectInfo, Host, Request, State},
+    http::{header::HOST, HeaderValue, StatusCode},
     response::{IntoResponse, Redirect, Response},
 };
-use hyper::client::conn::http1::Builder;
+use hyper::upgrade::OnUpgrade;
 use hyper_util::rt::TokioIo;
-use tokio::net::TcpStream;
-use tracing::error;
+use tracing::{debug, error, info_span, warn, Instrument, Span};
 
-use crate::env::state::{AppState, RouteTarget};
+use crate::env::state::{AppState, ClientAuthMode, RouteTarget, Service, UpstreamHost};
+use crate::proxy_protocol::Addresses;
 use crate::routes::static_files::serve_static_file;
+use crate::routing::{headers::HeaderVariables, path::normalize, RouteMatch};
+use crate::server::client_cert::ClientCertificate;
+use crate::telemetry;
+use crate::upstream::{
+    client::{remove_connection_headers, Sender},
+    connections::TrackedBody,
+    retry::{backoff, is_idempotent, RequestTemplate, RetryBudget},
+    timeout::{with_deadline, with_timeout, TimeoutBody, TimeoutKind},
+    Upstream,
+};
+use crate::utils::request_id::RequestId;
+
+/// Carries the labels matched by a wildcard domain to the upstream.
+const SUBDOMAIN_HEADER: &str = "x-forwarded-subdomain";
+
+/// What the proxy did with a request, attached to the response for access logging.
+#[derive(Debug, Clone)]
+pub struct RouteInfo {
+    pub route_type: &'static str,
+    pub upstream: Option<String>,
+}
 
 pub async fn proxy_handler(
     Host(host): Host,
     State(state): State<AppState>,
     req: Request,
-) -> Result<Response, StatusCode> {
-    let routes = state.routes_map.read().await;
+) -> Response {
+    let started_at = Instant::now();
+    let _in_flight = state.metrics.in_flight();
+    let method = req.method().clone();
     let domain = host.split(':').next().unwrap_or(&host);
 
-    let route_target = routes
-        .get(domain)
-        .or_else(|| routes.get("*"))
-        .ok_or(StatusCode::NOT_FOUND)?;
+    // Routes and static files are looked up by the normalized path, but upstreams get the
+    // request as the client sent it
+    let path = normalize(req.uri().path()).into_owned();
+    let route = state.route_table.read().await.find(domain, &path, &req);
+
+    let (route_label, service_label) = match &route {
+        Some(matched) => (
+            matched.route.label.as_str(),
+            matched.route.service().unwrap_or(""),
+        ),
+        None => ("", ""),
+    };
+    let span = Span::current();
+    span.record("route", route_label);
+    span.record("service", service_label);
+
+    let mut route_info = RouteInfo {
+        route_type: "none",
+        upstream: None,
+    };
+    let mut response = match &route {
+        Some(route) => handle_route(&state, domain, &path, route, req, &mut route_info).await,
+        None => StatusCode::NOT_FOUND.into_response(),
+    };
+
+    state.metrics.observe_request(
+        route_label,
+        service_label,
+        &method,
+        response.status(),
+        started_at.elapsed(),
+    );
+
+    if let Some(upstream) = &route_info.upstream {
+        span.record("upstream", upstream.as_str());
+    }
+
+    response.extensions_mut().insert(route_info);
+    response
+}
+
+/// Answers a matched request, applying the response header rules of the route to errors as
+/// well.
+async fn handle_route(
+    state: &AppState,
+    domain: &str,
+    path: &str,
+    matched: &RouteMatch,
+    req: Request,
+    route_info: &mut RouteInfo,
+) -> Response {
+    let variables = HeaderVariables {
+        client_ip: req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string()),
+        host: domain.to_string(),
+        request_id: req
+            .extensions()
+            .get::<RequestId>()
+            .map(|RequestId(id)| id.clone()),
+        subdomain: matched.subdomain.clone(),
+    };
+
+    let mut response = route_response(state, domain, path, matched, req, &variables, route_info)
+        .await
+        .unwrap_or_else(|status| status.into_response());
+    matched
+        .route
+        .headers
+        .response(response.headers_mut(), &variables);
+    response
+}
+
+async fn route_response(
+    state: &AppState,
+    domain: &str,
+    path: &str,
+    matched: &RouteMatch,
+    mut req: Request,
+    variables: &HeaderVariables,
+    route_info: &mut RouteInfo,
+) -> Result<Response, StatusCode> {
+    let route = &matched.route.route;
+    let client_cert = req.extensions().get::<ClientCertificate>().cloned();
+    ClientCertificate::remove_headers(req.headers_mut());
 
-    match route_target {
+    req.headers_mut().remove(SUBDOMAIN_HEADER);
+    if let Some(value) = matched
+        .subdomain
+        .as_ref()
+        .and_then(|subdomain| HeaderValue::from_str(subdomain).ok())
+    {
+        req.headers_mut().insert(SUBDOMAIN_HEADER, value);
+    }
+
+    if let Some(client_auth) = &route.client_auth {
+        match client_cert {
+            Some(cert) if cert.is_allowed(client_auth) => cert.insert_headers(req.headers_mut()),
+            Some(cert) => {
+                warn!(
+                    "Client certificate '{}' is not allowed for {}",
+                    cert.subject, domain
+                );
+                return Err(StatusCode::FORBIDDEN);
+            }
+            None if client_auth.mode == ClientAuthMode::Required => {
+                return Err(StatusCode::FORBIDDEN);
+            }
+            None => ClientCertificate::insert_missing_header(req.headers_mut()),
+        }
+    }
+
+    let route_headers = &matched.route.headers;
+
+    let response = match &route.target {
         RouteTarget::Service { service } => {
-            let services = state.services_map.read().await;
-            let service_config = services.get(service).ok_or(StatusCode::BAD_GATEWAY)?;
-            let target_addr = format!("{}:{}", service_config.host, service_config.port);
-            proxy_request(req, &target_addr).await
+            route_info.route_type = "service";
+
+            let mut service = state
+                .services_map
+                .read()
+                .await
+                .get(service)
+                .cloned()
+                .ok_or(StatusCode::BAD_GATEWAY)?;
+            if route.upstream_host.is_some() {
+                service.upstream_host = route.upstream_host.clone();
+            }
+            let service_headers = state.service_headers(&service.name).await;
+            // Taken before header rules can change it, for mapping Location headers back
+            let client_host = req
+                .headers()
+                .get(HOST)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or(domain)
+                .to_string();
+
+            if let Some(headers) = &service_headers {
+                headers.request(req.headers_mut(), variables);
+            }
+            route_headers.request(req.headers_mut(), variables);
+
+            let rewrite = matched.route.rewrite.as_ref();
+            if let Some(rewrite) = rewrite {
+                *req.uri_mut() = rewrite.uri(req.uri());
+            }
+
+            let mut response = proxy_to_service(state, req, &service, route_info)
+                .await
+                .unwrap_or_else(|status| status.into_response());
+            if let Some(rewrite) = rewrite {
+                let mut authorities = vec![client_host.as_str()];
+                authorities.extend(route_info.upstream.as_deref());
+                if let Some(UpstreamHost::Literal(host)) = &service.upstream_host {
+                    authorities.push(host);
+                }
+                rewrite.response_headers(response.headers_mut(), &authorities);
+            }
+            if let Some(headers) = &service_headers {
+                headers.response(response.headers_mut(), variables);
+            }
+            response
         }
         RouteTarget::Static {
             root,
             index,
             try_files,
         } => {
-            let path = req.uri().path();
+            route_info.route_type = "static";
+
             let default_index = vec!["index.html".to_string()];
             let index_files = if index.is_empty() {
                 &default_index
@@ -45,10 +228,13 @@ pub async fn proxy_handler(
             };
 
             serve_static_file(root, path, index_files, try_files)
-                .await
-                .map(|res| res.into_response())
+                .await?
+                .into_response()
         }
         RouteTarget::Redirect { to, code } => {
+            route_info.route_type = "redirect";
+
+            let to = &variables.render(to);
             let redirect = match *code {
                 301 => Redirect::permanent(to),
                 302 => Redirect::temporary(to),
@@ -57,45 +243,338 @@ pub async fn proxy_handler(
                 308 => Redirect::permanent(to),
                 _ => Redirect::to(to),
             };
-            Ok(redirect.into_response())
+            redirect.into_response()
+        }
+    };
+
+    Ok(response)
+}
+
+/// Why an upstream exchange failed. `NotSent` hands the request back, as nothing reached the
+/// upstream and it is safe to send it again whatever the method.
+pub enum UpstreamError {
+    NotSent(Box<Request>, StatusCode),
+    Failed(StatusCode),
+}
+
+impl UpstreamError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            UpstreamError::NotSent(_, status) | UpstreamError::Failed(status) => *status,
         }
     }
 }
 
-pub async fn proxy_request(mut req: Request, target: &str) -> Result<Response, StatusCode> {
-    let stream = TcpStream::connect(target).await.map_err(|e| {
-        error!("Failed to connect to {}: {}", target, e);
-        StatusCode::BAD_GATEWAY
-    })?;
-    let io = TokioIo::new(stream);
+/// Proxies to the endpoints of `service`, retrying according to its retry policy.
+async fn proxy_to_service(
+    state: &AppState,
+    mut req: Request,
+    service: &Service,
+    route_info: &mut RouteInfo,
+) -> Result<Response, StatusCode> {
+    let upstreams = state.upstreams(service).await;
+    let retry = match &service.retry {
+        Some(retry) => state
+            .retry_budget(&service.name)
+            .await
+            .map(|budget| (retry, budget)),
+        None => None,
+    };
+    let max_attempts = retry
+        .as_ref()
+        .map_or(1, |(retry, _)| retry.max_attempts.max(1));
+
+    // Only requests without a body can be replayed once the upstream has seen them
+    let template = (max_attempts > 1 && is_idempotent(req.method()) && req.body().is_end_stream())
+        .then(|| RequestTemplate::new(&req));
+
+    if let Some((_, budget)) = &retry {
+        budget.deposit();
+    }
+
+    let breaker = state.circuit_breaker_config(service).await;
+    // One total timeout for all attempts, so retries can't stretch it
+    let deadline = upstreams
+        .first()
+        .and_then(|upstream| upstream.timeouts.total)
+        .map(|total| tokio::time::Instant::now() + total);
+    let mut next_endpoint = 0;
+    let mut attempt = 1;
+    loop {
+        // Skip endpoints whose breaker is open, shedding the request when all of them are
+        let available = (next_endpoint..next_endpoint + upstreams.len()).find(|i| {
+            let upstream = &upstreams[i % upstreams.len()];
+            breaker.as_ref().is_none_or(|config| {
+                state
+                    .circuit_breakers
+                    .allow(&service.name, &upstream.address(), config)
+            })
+        });
+        let Some(index) = available else {
+            warn!(
+                "Circuit breakers of every endpoint of service '{}' are open",
+                service.name
+            );
+            state
+                .metrics
+                .circuit_breaker_rejections_total
+                .with_label_values(&[&service.name])
+                .inc();
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        };
+        next_endpoint = index + 1;
+
+        let upstream = &upstreams[index % upstreams.len()];
+        route_info.upstream = Some(upstream.address());
+
+        let result = proxy_request(state, req, upstream, deadline).await;
+        let success = match &result {
+            Ok(response) => Some(!response.status().is_server_error()),
+            // The request could not be prepared, so the upstream never saw it
+            Err(e) if e.status().is_client_error() => None,
+            Err(_) => Some(false),
+        };
+        if let (Some(config), Some(success)) = (&breaker, success) {
+            state
+                .circuit_breakers
+                .record(&service.name, &upstream.address(), config, success);
+        }
+        let delay = retry
+            .as_ref()
+            .map_or(Duration::ZERO, |(retry, _)| backoff(retry, attempt));
+        let out_of_time = deadline.is_some_and(|at| tokio::time::Instant::now() + delay >= at);
+        let policy = retry
+            .as_ref()
+            .filter(|_| attempt < max_attempts && !out_of_time);
+        let allow_retry = |budget: &RetryBudget| {
+            let allowed = budget.try_withdraw();
+            if !allowed {
+                warn!("Retry budget of service '{}' is exhausted", service.name);
+                state
+                    .metrics
+                    .retry_budget_exhausted_total
+                    .with_label_values(&[&service.name])
+                    .inc();
+            }
+            allowed
+        };
+
+        let (next, reason) = match (result, policy, &template) {
+            (Ok(response), Some((retry, budget)), Some(template))
+                if retry.retry_on_status.contains(&response.status().as_u16())
+                    && allow_retry(budget) =>
+            {
+                (template.build(), response.status().as_str().to_string())
+            }
+            (Err(UpstreamError::NotSent(req, _)), Some((_, budget)), _) if allow_retry(budget) => {
+                (*req, "connect".to_string())
+            }
+            (Ok(response), _, _) => return Ok(response),
+            (Err(e), _, _) => return Err(e.status()),
+        };
 
-    let (mut sender, conn) = Builder::new()
-        .preserve_header_case(true)
-        .title_case_headers(true)
-        .handshake(io)
+        state
+            .metrics
+            .upstream_retries_total
+            .with_label_values(&[&service.name, &reason])
+            .inc();
+
+        warn!(
+            "Retrying request to service '{}' in {:?} (reason: {}, upstream: {}, attempt {} of {})",
+            service.name,
+            delay,
+            reason,
+            upstream,
+            attempt + 1,
+            max_attempts
+        );
+        tokio::time::sleep(delay).await;
+
+        req = next;
+        attempt += 1;
+    }
+}
+
+/// Sends `req` to `upstream`, giving up at `deadline`, the end of the service's total timeout
+/// for every attempt together.
+pub async fn proxy_request(
+    state: &AppState,
+    req: Request,
+    upstream: &Upstream,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<Response, UpstreamError> {
+    let span = info_span!(
+        "upstream",
+        otel.name = %format!("{} {}", req.method(), upstream.service),
+        otel.kind = "client",
+        server.address = %upstream.host,
+        server.port = upstream.port,
+        http.response.status_code = tracing::field::Empty,
+    );
+
+    async move {
+        let response = with_deadline(
+            TimeoutKind::Total,
+            upstream.timeouts.total.zip(deadline),
+            send_upstream(state, req, upstream, deadline),
+        )
         .await
         .map_err(|e| {
+            error!("Request to {} failed: {}", upstream, e);
+            UpstreamError::Failed(StatusCode::GATEWAY_TIMEOUT)
+        })??;
+        Span::current().record("http.response.status_code", response.status().as_u16());
+        Ok(response)
+    }
+    .instrument(span)
+    .await
+}
+
+async fn send_upstream(
+    state: &AppState,
+    mut req: Request,
+    upstream: &Upstream,
+    deadline: Option<tokio::time::Instant>,
+) -> Result<Response, UpstreamError> {
+    let started_at = Instant::now();
+    telemetry::inject_headers(&Span::current(), req.headers_mut());
+    let active_request = state
+        .active_connections
+        .request(&upstream.service, &upstream.address());
+
+    let client_host = req
+        .headers()
+        .get(HOST)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| req.uri().authority().map(|authority| authority.as_str()))
+        .map(String::from);
+    let host = upstream.request_host(client_host.as_deref());
+    if let Some(value) = host
+        .as_deref()
+        .and_then(|host| HeaderValue::from_str(host).ok())
+    {
+        req.headers_mut().insert(HOST, value);
+    }
+
+    let server_name = upstream.server_name(host.as_deref());
+    let client = req.extensions().get::<Addresses>().copied();
+    let stream = match upstream
+        .connect(server_name.as_deref(), client.as_ref())
+        .await
+    {
+        Ok(stream) => stream,
+        Err(e) => {
+            error!("Failed to connect to {}: {}", upstream, e);
+            state
+                .metrics
+                .upstream_connect_errors_total
+                .with_label_values(&[&upstream.service, &upstream.address()])
+                .inc();
+
+            let status = if e.kind() == io::ErrorKind::TimedOut {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            return Err(UpstreamError::NotSent(Box::new(req), status));
+        }
+    };
+    let (mut sender, conn) = match Sender::handshake(upstream, stream).await {
+        Ok(handshake) => handshake,
+        Err(e) => {
             error!("Handshake error: {}", e);
-            StatusCode::BAD_GATEWAY
-        })?;
+            return Err(UpstreamError::NotSent(
+                Box::new(req),
+                StatusCode::BAD_GATEWAY,
+            ));
+        }
+    };
 
+    let active_connection = state
+        .active_connections
+        .connection(&upstream.service, &upstream.address());
+    // Not in the request's span, which would otherwise stay open as long as the connection
+    let connection_name = upstream.to_string();
     tokio::spawn(async move {
+        let _active_connection = active_connection;
         if let Err(err) = conn.await {
-            error!("Connection error: {}", err);
+            error!("Connection to {} failed: {}", connection_name, err);
         }
     });
 
-    let uri = req.uri();
-    let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
-
-    *req.uri_mut() = path_and_query
-        .parse::<Uri>()
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let client_upgrade = sender
+        .prepare(upstream, &mut req)
+        .map_err(|_| UpstreamError::Failed(StatusCode::BAD_REQUEST))?;
 
-    let response = sender.send_request(req).await.map_err(|e| {
-        error!("Request error: {}", e);
-        StatusCode::BAD_GATEWAY
+    let response = with_timeout(
+        TimeoutKind::FirstByte,
+        upstream.timeouts.first_byte,
+        sender.try_send_request(req),
+    )
+    .await
+    .map_err(|e| {
+        error!("Request to {} failed: {}", upstream, e);
+        UpstreamError::Failed(StatusCode::GATEWAY_TIMEOUT)
     })?;
 
-    Ok(response.into_response())
+    let mut response = match response {
+        Ok(response) => response,
+        Err(mut e) => {
+            let req = e.take_message();
+            error!("Request error: {}", e.into_error());
+            return Err(match req {
+                Some(req) => UpstreamError::NotSent(Box::new(req), StatusCode::BAD_GATEWAY),
+                None => UpstreamError::Failed(StatusCode::BAD_GATEWAY),
+            });
+        }
+    };
+
+    state
+        .metrics
+        .upstream_duration_seconds
+        .with_label_values(&[&upstream.service])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let Some(client_upgrade) = client_upgrade else {
+            error!("{} switched protocols without being asked to", upstream);
+            return Err(UpstreamError::Failed(StatusCode::BAD_GATEWAY));
+        };
+        remove_connection_headers(response.headers_mut(), false, true);
+
+        let upstream_upgrade = hyper::upgrade::on(&mut response);
+        let connection = state.shutdown.connection();
+        let upstream_name = upstream.to_string();
+        tokio::spawn(
+            async move {
+                // Counted as an active request and client connection until either side closes
+                let _active_request = active_request;
+                let _connection = connection;
+                if let Err(e) = tunnel(client_upgrade, upstream_upgrade).await {
+                    debug!("Upgraded connection to {} closed: {}", upstream_name, e);
+                }
+            }
+            .in_current_span(),
+        );
+
+        return Ok(response.map(|_| Body::empty()).into_response());
+    }
+
+    remove_connection_headers(response.headers_mut(), false, false);
+
+    let upstream_name = upstream.to_string();
+    let timeouts = upstream.timeouts;
+    Ok(response
+        .map(|body| {
+            let body = TimeoutBody::new(body, upstream_name, &timeouts, deadline);
+            Body::new(TrackedBody::new(body, active_request))
+        })
+        .into_response())
+}
+
+/// Copies bytes both ways between the client and the upstream once both switched protocols.
+async fn tunnel(client: OnUpgrade, upstream: OnUpgrade) -> io::Result<()> {
+    let (client, upstream) = tokio::try_join!(client, upstream).map_err(io::Error::other)?;
+    tokio::io::copy_bidirectional(&mut TokioIo::new(client), &mut TokioIo::new(upstream)).await?;
+    Ok(())
 }
@@ -0,0 +1,19 @@
+use axum::{extract::State, response::IntoResponse, Json};
+use hyper::StatusCode;
+
+use crate::env::state::AppState;
+
+/// Readiness probe for load balancers, failing as soon as a shutdown starts.
+pub async fn get(State(state): State<AppState>) -> impl IntoResponse {
+    if state.shutdown.is_triggered() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({ "status": "shutting_down" })),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "status": "ready" })),
+    )
+}
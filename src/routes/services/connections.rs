@@ -0,0 +1,35 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Json,
+};
+use hyper::StatusCode;
+
+use crate::env::state::AppState;
+
+pub async fn get(State(state): State<AppState>, Path(name): Path<String>) -> impl IntoResponse {
+    let Some(service) = state.services_map.read().await.get(&name).cloned() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "error": format!("Service '{}' not found", name)
+            })),
+        );
+    };
+
+    let connections = &state.active_connections;
+    let previous = service
+        .previous_port
+        .map(|port| connections.counts(&service.addresses(port)));
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({
+            "service": service.name,
+            "current_port": service.port,
+            "previous_port": service.previous_port,
+            "current": connections.counts(&service.addresses(service.port)),
+            "previous": previous,
+        })),
+    )
+}
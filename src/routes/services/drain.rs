@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    response::IntoResponse,
+    Json,
+};
+use hyper::StatusCode;
+use serde::Deserialize;
+
+use crate::env::state::AppState;
+
+#[derive(Deserialize)]
+pub struct DrainQuery {
+    /// Port to wait for, the service's `previous_port` by default
+    pub port: Option<u16>,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_timeout_seconds() -> u64 {
+    30
+}
+
+/// Waits until no request or connection is left on the old port of a service.
+pub async fn get(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<DrainQuery>,
+) -> impl IntoResponse {
+    let Some(service) = state.services_map.read().await.get(&name).cloned() else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "error": format!("Service '{}' not found", name)
+            })),
+        );
+    };
+
+    let Some(port) = query.port.or(service.previous_port) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "error": format!("Service '{}' has no previous port to drain", name)
+            })),
+        );
+    };
+
+    let addresses = service.addresses(port);
+    let drained = state
+        .active_connections
+        .wait_drained(&addresses, Duration::from_secs(query.timeout_seconds))
+        .await;
+    let remaining = state.active_connections.counts(&addresses);
+
+    if drained {
+        log::info!("Service '{}' drained on port {}", name, port);
+    } else {
+        log::warn!(
+            "Service '{}' did not drain on port {} within {}s ({} requests, {} connections left)",
+            name,
+            port,
+            query.timeout_seconds,
+            remaining.requests,
+            remaining.connections
+        );
+    }
+
+    let status = if drained {
+        StatusCode::OK
+    } else {
+        StatusCode::GATEWAY_TIMEOUT
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "service": name,
+            "port": port,
+            "drained": drained,
+            "remaining": remaining,
+        })),
+    )
+}
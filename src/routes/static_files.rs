@@ -42,8 +42,8 @@ pub async fn serve_static_file(
 
     if !file_path.exists() && !try_files.is_empty() {
         for try_file in try_files {
-            let try_path = if try_file.starts_with('/') {
-                PathBuf::from(root).join(&try_file[1..])
+            let try_path = if let Some(absolute) = try_file.strip_prefix('/') {
+                PathBuf::from(root).join(absolute)
             } else {
                 file_path.parent().unwrap_or(&file_path).join(try_file)
             };
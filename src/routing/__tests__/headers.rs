@@ -0,0 +1,53 @@
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+
+    use crate::env::state::HeaderRules;
+    use crate::routing::headers::{HeaderVariables, Headers};
+
+    #[test]
+    fn should_remove_set_and_add_headers() {
+        let rules: HeaderRules = serde_yaml::from_str(
+            r#"
+request:
+  remove: [cookie]
+  set: { x-internal-auth: secret, x-client: "$client_ip via $host" }
+  add: { x-trace: "$request_id" }
+response:
+  remove: [server]
+"#,
+        )
+        .unwrap();
+        let headers = Headers::compile(&rules).unwrap();
+        let variables = HeaderVariables {
+            client_ip: Some("10.0.0.1".into()),
+            host: "example.com".into(),
+            request_id: Some("abc".into()),
+            subdomain: None,
+        };
+
+        let mut request = HeaderMap::new();
+        request.insert("cookie", "a=b".parse().unwrap());
+        request.insert("x-internal-auth", "spoofed".parse().unwrap());
+        request.insert("x-trace", "upstream".parse().unwrap());
+        headers.request(&mut request, &variables);
+
+        assert!(request.get("cookie").is_none());
+        assert_eq!(request["x-internal-auth"], "secret");
+        assert_eq!(request["x-client"], "10.0.0.1 via example.com");
+        assert_eq!(request.get_all("x-trace").iter().count(), 2);
+
+        let mut response = HeaderMap::new();
+        response.insert("server", "py".parse().unwrap());
+        headers.response(&mut response, &variables);
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn should_reject_invalid_header_names() {
+        let rules: HeaderRules =
+            serde_yaml::from_str("response: { remove: ['bad header'] }").unwrap();
+
+        assert!(Headers::compile(&rules).is_err());
+    }
+}
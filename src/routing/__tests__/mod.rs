@@ -0,0 +1,3 @@
+mod headers;
+mod rewrite;
+mod table;
@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod tests {
+    use crate::env::state::RewriteConfig;
+    use crate::routing::rewrite::Rewrite;
+
+    /// The client's host and the service address
+    const AUTHORITIES: &[&str] = &["example.com", "10.0.0.5:80"];
+
+    fn rewrite(yaml: &str) -> Rewrite {
+        let config: RewriteConfig = serde_yaml::from_str(yaml).unwrap();
+        Rewrite::compile(&config).unwrap()
+    }
+
+    #[test]
+    fn should_strip_and_add_prefixes() {
+        let rewrite = rewrite("{ strip_prefix: /billing/, add_prefix: /internal }");
+
+        assert_eq!(rewrite.path("/billing"), "/internal/");
+        assert_eq!(rewrite.path("/billing/invoices"), "/internal/invoices");
+        assert_eq!(rewrite.path("/billingx"), "/internal/billingx");
+        assert_eq!(
+            rewrite.uri(&"/billing/a?b=c".parse().unwrap()).to_string(),
+            "/internal/a?b=c"
+        );
+
+        assert_eq!(
+            rewrite
+                .location("/internal/login?next=1", AUTHORITIES)
+                .as_deref(),
+            Some("/billing/login?next=1")
+        );
+        assert_eq!(
+            rewrite
+                .location("https://example.com/internal", AUTHORITIES)
+                .as_deref(),
+            Some("https://example.com/billing/")
+        );
+        assert_eq!(
+            rewrite
+                .location("http://10.0.0.5/internal/a", AUTHORITIES)
+                .as_deref(),
+            Some("http://10.0.0.5/billing/a")
+        );
+        assert_eq!(
+            rewrite.location("https://accounts.example.org/internal", AUTHORITIES),
+            None
+        );
+        assert_eq!(
+            rewrite.location("https://example.com:8443/internal", AUTHORITIES),
+            None
+        );
+        assert_eq!(rewrite.location("/elsewhere", AUTHORITIES), None);
+        assert_eq!(rewrite.location("relative", AUTHORITIES), None);
+    }
+
+    #[test]
+    fn should_replace_with_captures() {
+        let rewrite = rewrite(r#"{ regex: "^/v(\\d+)/(.*)$", replacement: "/api/$2?version=$1" }"#);
+
+        assert_eq!(rewrite.path("/v2/users"), "/api/users?version=2");
+        assert_eq!(rewrite.path("/other"), "/other");
+        assert_eq!(
+            rewrite.uri(&"/v1/a?b=c".parse().unwrap()).to_string(),
+            "/api/a?version=1&b=c"
+        );
+        assert_eq!(rewrite.location("/api/users", AUTHORITIES), None);
+    }
+}
@@ -0,0 +1,235 @@
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use crate::env::state::Route;
+    use crate::routing::path::normalize;
+    use crate::routing::{RouteMatch, RouteTable};
+
+    fn table(yaml: &str) -> RouteTable {
+        let routes: Vec<Route> = serde_yaml::from_str(yaml).unwrap();
+        RouteTable::new(&routes).unwrap()
+    }
+
+    fn request(uri: &str) -> Request<()> {
+        Request::get(uri).body(()).unwrap()
+    }
+
+    fn find(table: &RouteTable, host: &str, req: &Request<()>) -> Option<RouteMatch> {
+        table.find(host, &normalize(req.uri().path()), req)
+    }
+
+    fn service(table: &RouteTable, host: &str, path: &str) -> Option<String> {
+        find(table, host, &request(path))
+            .and_then(|matched| matched.route.service().map(String::from))
+    }
+
+    #[test]
+    fn should_prefer_the_most_specific_path() {
+        let table = table(
+            r#"
+- { domain: example.com, type: service, service: web }
+- { domain: example.com, path: { prefix: /api }, type: service, service: api }
+- { domain: example.com, path: { prefix: /api/v2 }, type: service, service: api-v2 }
+- { domain: example.com, path: { exact: /api/health }, type: service, service: health }
+- { domain: example.com, path: { glob: "/assets/**/*.css" }, type: service, service: css }
+- { domain: "*", type: service, service: fallback }
+"#,
+        );
+
+        assert_eq!(service(&table, "example.com", "/"), Some("web".into()));
+        assert_eq!(service(&table, "example.com", "/api"), Some("api".into()));
+        assert_eq!(service(&table, "example.com", "/apis"), Some("web".into()));
+        assert_eq!(
+            service(&table, "example.com", "/api/v2/users"),
+            Some("api-v2".into())
+        );
+        assert_eq!(
+            service(&table, "example.com", "/api/health"),
+            Some("health".into())
+        );
+        assert_eq!(
+            service(&table, "example.com", "/assets/a/b/site.css"),
+            Some("css".into())
+        );
+        assert_eq!(
+            service(&table, "example.com", "/assets/site.js"),
+            Some("web".into())
+        );
+        assert_eq!(
+            service(&table, "other.com", "/api"),
+            Some("fallback".into())
+        );
+    }
+
+    #[test]
+    fn should_honour_explicit_priority() {
+        let table = table(
+            r#"
+- { domain: example.com, path: { prefix: /api/v2 }, type: service, service: api-v2 }
+- { domain: example.com, path: { regex: "^/api/" }, priority: 10, type: service, service: api }
+"#,
+        );
+
+        assert_eq!(
+            service(&table, "example.com", "/api/v2/users"),
+            Some("api".into())
+        );
+        assert_eq!(service(&table, "example.com", "/api"), None);
+    }
+
+    #[test]
+    fn should_match_wildcard_subdomains() {
+        let table = table(
+            r#"
+- { domain: "*.example.com", type: service, service: tenant }
+- { domain: "*.eu.example.com", type: service, service: eu }
+- { domain: www.example.com, type: service, service: www }
+- { domain: api.example.com, path: { prefix: /v1 }, type: service, service: api }
+"#,
+        );
+
+        let matched = find(&table, "Foo.Example.com", &request("/")).unwrap();
+        assert_eq!(matched.route.service(), Some("tenant"));
+        assert_eq!(matched.subdomain.as_deref(), Some("foo"));
+
+        let matched = find(&table, "a.b.eu.example.com", &request("/")).unwrap();
+        assert_eq!(matched.route.service(), Some("eu"));
+        assert_eq!(matched.subdomain.as_deref(), Some("a.b"));
+
+        assert_eq!(service(&table, "www.example.com", "/"), Some("www".into()));
+        assert_eq!(
+            service(&table, "api.example.com", "/v1/x"),
+            Some("api".into())
+        );
+        assert_eq!(
+            service(&table, "api.example.com", "/v2"),
+            Some("tenant".into())
+        );
+        assert_eq!(service(&table, "example.com", "/"), None);
+    }
+
+    #[test]
+    fn should_try_routes_with_conditions_first() {
+        let table = table(
+            r#"
+- domain: example.com
+  type: service
+  service: stable
+- domain: example.com
+  conditions:
+    headers: [{ name: x-canary, equals: "1" }]
+  type: service
+  service: canary
+- domain: example.com
+  conditions:
+    methods: [post]
+    query: [{ name: version, regex: "^v[23]$" }]
+    cookies: [{ name: beta }]
+  type: service
+  service: beta
+"#,
+        );
+        let find = |req: Request<()>| {
+            find(&table, "example.com", &req)
+                .and_then(|matched| matched.route.service().map(String::from))
+        };
+
+        assert_eq!(find(request("/")), Some("stable".into()));
+
+        let req = Request::get("/").header("X-Canary", "1").body(()).unwrap();
+        assert_eq!(find(req), Some("canary".into()));
+
+        let req = Request::post("/?a=b&version=v2")
+            .header("cookie", "theme=dark; beta=yes")
+            .body(())
+            .unwrap();
+        assert_eq!(find(req), Some("beta".into()));
+
+        let req = Request::get("/?version=v2")
+            .header("cookie", "beta=yes")
+            .body(())
+            .unwrap();
+        assert_eq!(find(req), Some("stable".into()));
+    }
+
+    #[test]
+    fn should_reject_invalid_patterns() {
+        let routes: Vec<Route> = serde_yaml::from_str(
+            "- { domain: example.com, path: { regex: '(' }, type: service, service: api }",
+        )
+        .unwrap();
+
+        assert!(RouteTable::new(&routes).is_err());
+    }
+
+    #[test]
+    fn should_pass_through_to_the_catch_all_route_of_the_domain() {
+        let table = table(
+            r#"
+- { domain: example.com, path: { prefix: /api }, type: service, service: api }
+- { domain: example.com, type: service, service: web }
+- { domain: "*.example.com", type: service, service: tenants }
+- { domain: static.example.com, type: static, root: /srv/www }
+- { domain: "*", type: service, service: fallback }
+"#,
+        );
+
+        assert_eq!(table.passthrough_service("example.com"), Some("web"));
+        assert_eq!(
+            table.passthrough_service("a.b.example.com"),
+            Some("tenants")
+        );
+        assert_eq!(table.passthrough_service("static.example.com"), None);
+        assert_eq!(table.passthrough_service(""), Some("fallback"));
+    }
+
+    #[test]
+    fn should_match_routes_on_the_normalized_path() {
+        let table = table(
+            r#"
+- { domain: example.com, type: service, service: web }
+- { domain: example.com, path: { prefix: /admin }, type: service, service: admin }
+"#,
+        );
+
+        for path in [
+            "/admin",
+            "//admin",
+            "/./admin",
+            "/%61dmin",
+            "/%2e/admin",
+            "/public/../admin/",
+            "/../../admin",
+            "/admin//users?page=2",
+        ] {
+            assert_eq!(
+                service(&table, "example.com", path),
+                Some("admin".into()),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            service(&table, "example.com", "/admin%2Fusers"),
+            Some("web".into())
+        );
+    }
+
+    #[test]
+    fn should_normalize_paths() {
+        for (path, normalized) in [
+            ("/", "/"),
+            ("/a/b/", "/a/b/"),
+            ("//a///b", "/a/b"),
+            ("/a/./b/.", "/a/b/"),
+            ("/a/b/../../..", "/"),
+            ("/a/..b/.c", "/a/..b/.c"),
+            ("/%7Euser/%41%2f%2e%2E", "/~user/A%2F.."),
+            ("/%zz/%", "/%zz/%"),
+            ("/caf%C3%A9", "/caf%C3%A9"),
+        ] {
+            assert_eq!(normalize(path), normalized, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use axum::http::{header::COOKIE, Request};
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+
+use crate::env::state::{RouteConditions, ValueCondition};
+
+/// Compiled `RouteConditions`.
+#[derive(Debug, Default)]
+pub struct Conditions {
+    methods: Vec<String>,
+    headers: Vec<ValueMatcher>,
+    query: Vec<ValueMatcher>,
+    cookies: Vec<ValueMatcher>,
+}
+
+impl Conditions {
+    pub fn compile(conditions: &RouteConditions) -> Result<Self, String> {
+        let compile_all = |conditions: &[ValueCondition]| {
+            conditions
+                .iter()
+                .map(ValueMatcher::compile)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            methods: conditions
+                .methods
+                .iter()
+                .map(|method| method.to_ascii_uppercase())
+                .collect(),
+            headers: compile_all(&conditions.headers)?,
+            query: compile_all(&conditions.query)?,
+            cookies: compile_all(&conditions.cookies)?,
+        })
+    }
+
+    pub fn matches<B>(&self, req: &Request<B>) -> bool {
+        if !self.methods.is_empty() && !self.methods.iter().any(|m| m == req.method().as_str()) {
+            return false;
+        }
+
+        let headers = |name: &str| {
+            req.headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        if !self.headers.iter().all(|m| m.matches(headers(&m.name))) {
+            return false;
+        }
+
+        let query = req.uri().query().unwrap_or("");
+        if !self
+            .query
+            .iter()
+            .all(|m| m.matches(query_values(query, &m.name)))
+        {
+            return false;
+        }
+
+        let cookies = headers(COOKIE.as_str());
+        self.cookies
+            .iter()
+            .all(|m| m.matches(cookie_values(&cookies, &m.name)))
+    }
+}
+
+#[derive(Debug)]
+struct ValueMatcher {
+    name: String,
+    equals: Option<String>,
+    regex: Option<Regex>,
+}
+
+impl ValueMatcher {
+    fn compile(condition: &ValueCondition) -> Result<Self, String> {
+        let regex = condition
+            .regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| format!("Invalid regex for '{}': {}", condition.name, e))?;
+
+        Ok(Self {
+            name: condition.name.clone(),
+            equals: condition.equals.clone(),
+            regex,
+        })
+    }
+
+    /// Matches when any of the values satisfies every test.
+    fn matches(&self, values: Vec<String>) -> bool {
+        values.iter().any(|value| {
+            self.equals.as_ref().is_none_or(|equals| value == equals)
+                && self
+                    .regex
+                    .as_ref()
+                    .is_none_or(|regex| regex.is_match(value))
+        })
+    }
+}
+
+fn query_values(query: &str, name: &str) -> Vec<String> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let key = percent_decode_str(&key.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned();
+            (key == name).then(|| {
+                percent_decode_str(&value.replace('+', " "))
+                    .decode_utf8_lossy()
+                    .into_owned()
+            })
+        })
+        .collect()
+}
+
+fn cookie_values(headers: &[String], name: &str) -> Vec<String> {
+    headers
+        .iter()
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| {
+            let (key, value) = cookie.trim().split_once('=')?;
+            (key == name).then(|| value.trim_matches('"').to_string())
+        })
+        .collect()
+}
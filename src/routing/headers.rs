@@ -0,0 +1,109 @@
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::env::state::{HeaderActions, HeaderRules};
+use crate::utils::template;
+
+/// Compiled `HeaderRules`.
+#[derive(Debug, Default)]
+pub struct Headers {
+    request: Actions,
+    response: Actions,
+}
+
+impl Headers {
+    pub fn compile(rules: &HeaderRules) -> Result<Self, String> {
+        Ok(Self {
+            request: Actions::compile(&rules.request)?,
+            response: Actions::compile(&rules.response)?,
+        })
+    }
+
+    pub fn request(&self, headers: &mut HeaderMap, variables: &HeaderVariables) {
+        self.request.apply(headers, variables);
+    }
+
+    pub fn response(&self, headers: &mut HeaderMap, variables: &HeaderVariables) {
+        self.response.apply(headers, variables);
+    }
+}
+
+#[derive(Debug, Default)]
+struct Actions {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, String)>,
+    add: Vec<(HeaderName, String)>,
+}
+
+impl Actions {
+    fn compile(actions: &HeaderActions) -> Result<Self, String> {
+        let name = |name: &str| {
+            HeaderName::try_from(name).map_err(|e| format!("Invalid header '{}': {}", name, e))
+        };
+        let values = |values: &std::collections::BTreeMap<String, String>| {
+            values
+                .iter()
+                .map(|(header, value)| {
+                    // Rendered per request, but a value without variables must be valid as is
+                    HeaderValue::from_str(value)
+                        .map_err(|e| format!("Invalid value for header '{}': {}", header, e))?;
+                    Ok((name(header)?, value.clone()))
+                })
+                .collect::<Result<Vec<_>, String>>()
+        };
+
+        Ok(Self {
+            remove: actions
+                .remove
+                .iter()
+                .map(|header| name(header))
+                .collect::<Result<_, _>>()?,
+            set: values(&actions.set)?,
+            add: values(&actions.add)?,
+        })
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, variables: &HeaderVariables) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+
+        for (name, value) in &self.set {
+            if let Some(value) = variables.header_value(value) {
+                headers.insert(name.clone(), value);
+            }
+        }
+
+        for (name, value) in &self.add {
+            if let Some(value) = variables.header_value(value) {
+                headers.append(name.clone(), value);
+            }
+        }
+    }
+}
+
+/// Request attributes available to header values and redirect targets.
+#[derive(Debug, Default)]
+pub struct HeaderVariables {
+    pub client_ip: Option<String>,
+    pub host: String,
+    pub request_id: Option<String>,
+    pub subdomain: Option<String>,
+}
+
+impl HeaderVariables {
+    /// Substitutes the variables in `template`. `$subdomain` is empty when the route has no
+    /// wildcard, unknown variables are left as they are.
+    pub fn render(&self, template: &str) -> String {
+        template::render(template, |name| match name {
+            "client_ip" => self.client_ip.clone(),
+            "host" => Some(self.host.clone()),
+            "request_id" => self.request_id.clone(),
+            "subdomain" => Some(self.subdomain.clone().unwrap_or_default()),
+            _ => None,
+        })
+    }
+
+    fn header_value(&self, value: &str) -> Option<HeaderValue> {
+        HeaderValue::from_str(&self.render(value)).ok()
+    }
+}
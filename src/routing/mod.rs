@@ -0,0 +1,215 @@
+mod __tests__;
+
+use std::{collections::HashMap, sync::Arc};
+
+use axum::http::Request;
+
+use crate::env::state::{ClientAuthMode, Route, RouteTarget};
+
+use self::conditions::Conditions;
+use self::headers::Headers;
+use self::path::{specificity, PathMatcher};
+use self::rewrite::Rewrite;
+
+pub mod conditions;
+pub mod headers;
+pub mod path;
+pub mod rewrite;
+
+/// A route prepared for matching.
+#[derive(Debug)]
+pub struct CompiledRoute {
+    pub route: Route,
+    /// Identifies the route in metrics and traces, e.g. `api.example.com/v2`
+    pub label: String,
+    path: Option<PathMatcher>,
+    conditions: Conditions,
+    pub rewrite: Option<Rewrite>,
+    pub headers: Headers,
+}
+
+impl CompiledRoute {
+    fn compile(route: &Route) -> Result<Self, String> {
+        let path = route
+            .path
+            .as_ref()
+            .map(PathMatcher::compile)
+            .transpose()
+            .map_err(|e| format!("Route '{}': {}", route.domain, e))?;
+        let conditions = Conditions::compile(&route.conditions)
+            .map_err(|e| format!("Route '{}': {}", route.domain, e))?;
+        let rewrite = route
+            .rewrite
+            .as_ref()
+            .map(Rewrite::compile)
+            .transpose()
+            .map_err(|e| format!("Route '{}': {}", route.domain, e))?;
+        let headers = Headers::compile(&route.headers)
+            .map_err(|e| format!("Route '{}': {}", route.domain, e))?;
+
+        let label = match &route.path {
+            Some(path) => format!("{}{}", route.domain, path_label(path)),
+            None => route.domain.clone(),
+        };
+
+        Ok(Self {
+            route: route.clone(),
+            label,
+            path,
+            conditions,
+            rewrite,
+            headers,
+        })
+    }
+
+    fn matches<B>(&self, path: &str, req: &Request<B>) -> bool {
+        self.path
+            .as_ref()
+            .is_none_or(|matcher| matcher.matches(path))
+            && self.conditions.matches(req)
+    }
+
+    pub fn service(&self) -> Option<&str> {
+        match &self.route.target {
+            RouteTarget::Service { service } => Some(service),
+            _ => None,
+        }
+    }
+}
+
+fn path_label(path: &crate::env::state::PathMatch) -> &str {
+    use crate::env::state::PathMatch;
+
+    match path {
+        PathMatch::Exact(path)
+        | PathMatch::Prefix(path)
+        | PathMatch::Glob(path)
+        | PathMatch::Regex(path) => path,
+    }
+}
+
+/// The route chosen for a request.
+#[derive(Debug, Clone)]
+pub struct RouteMatch {
+    pub route: Arc<CompiledRoute>,
+    /// The labels matched by the `*` of a wildcard domain, e.g. `foo` for `foo.example.com`
+    /// under `*.example.com`
+    pub subdomain: Option<String>,
+}
+
+/// All routes grouped by domain, each group sorted in the order it is matched.
+#[derive(Debug, Default)]
+pub struct RouteTable {
+    domains: HashMap<String, Vec<Arc<CompiledRoute>>>,
+}
+
+impl RouteTable {
+    pub fn new(routes: &[Route]) -> Result<Self, String> {
+        let mut domains: HashMap<String, Vec<(usize, Arc<CompiledRoute>)>> = HashMap::new();
+
+        for (index, route) in routes.iter().enumerate() {
+            domains
+                .entry(route.domain.to_ascii_lowercase())
+                .or_default()
+                .push((index, Arc::new(CompiledRoute::compile(route)?)));
+        }
+
+        let domains = domains
+            .into_iter()
+            .map(|(domain, mut routes)| {
+                // Highest priority first, then most specific path, then most conditions, then
+                // configuration order
+                routes.sort_by_key(|(index, compiled)| {
+                    let (rank, length) = specificity(compiled.route.path.as_ref());
+                    (
+                        std::cmp::Reverse(compiled.route.priority),
+                        std::cmp::Reverse(rank),
+                        std::cmp::Reverse(length),
+                        std::cmp::Reverse(compiled.route.conditions.len()),
+                        *index,
+                    )
+                });
+                (domain, routes.into_iter().map(|(_, route)| route).collect())
+            })
+            .collect();
+
+        Ok(Self { domains })
+    }
+
+    /// Finds the route for a request, trying the routes of the exact domain, then wildcard domains
+    /// from the longest suffix to the shortest, then the `*` catch-all. Paths are matched on
+    /// `path`, the normalized path of `req`.
+    pub fn find<B>(&self, host: &str, path: &str, req: &Request<B>) -> Option<RouteMatch> {
+        self.candidates(host)
+            .into_iter()
+            .find_map(|(routes, subdomain)| {
+                routes
+                    .iter()
+                    .find(|route| route.matches(path, req))
+                    .map(|route| RouteMatch {
+                        route: route.clone(),
+                        subdomain,
+                    })
+            })
+    }
+
+    /// Service for a TLS connection passed through by SNI, where only the host is known: the route
+    /// without path or conditions of the most specific domain matching `host`.
+    pub fn passthrough_service(&self, host: &str) -> Option<&str> {
+        self.candidates(host)
+            .into_iter()
+            .find_map(|(routes, _)| {
+                routes
+                    .iter()
+                    .find(|route| route.path.is_none() && route.route.conditions.is_empty())
+            })?
+            .service()
+    }
+
+    /// Client certificate mode for a TLS handshake with `host`, before the path is known.
+    /// Certificates are required only when every route of the domain requires them.
+    pub fn client_auth_mode(&self, host: &str) -> Option<ClientAuthMode> {
+        let (routes, _) = self.candidates(host).into_iter().next()?;
+        let modes: Vec<Option<ClientAuthMode>> = routes
+            .iter()
+            .map(|route| route.route.client_auth.as_ref().map(|auth| auth.mode))
+            .collect();
+
+        if modes.iter().all(Option::is_none) {
+            None
+        } else if modes
+            .iter()
+            .all(|mode| *mode == Some(ClientAuthMode::Required))
+        {
+            Some(ClientAuthMode::Required)
+        } else {
+            Some(ClientAuthMode::Optional)
+        }
+    }
+
+    /// Route groups that apply to `host`, most specific first, with the labels matched by `*`.
+    fn candidates(&self, host: &str) -> Vec<(&Vec<Arc<CompiledRoute>>, Option<String>)> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let mut candidates: Vec<_> = self
+            .domains
+            .get(&host)
+            .map(|r| (r, None))
+            .into_iter()
+            .collect();
+
+        // `*.example.com` covers `a.example.com` and `a.b.example.com`, but `*.b.example.com`
+        // is more specific for the latter
+        for (dot, _) in host.match_indices('.') {
+            let suffix = &host[dot + 1..];
+            if let Some(routes) = self.domains.get(&format!("*.{}", suffix)) {
+                candidates.push((routes, Some(host[..dot].to_string())));
+            }
+        }
+
+        if let Some(routes) = self.domains.get("*") {
+            candidates.push((routes, None));
+        }
+
+        candidates
+    }
+}
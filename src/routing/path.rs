@@ -0,0 +1,143 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+
+use crate::env::state::PathMatch;
+
+/// A compiled `PathMatch`.
+#[derive(Debug)]
+pub enum PathMatcher {
+    Exact(String),
+    Prefix(String),
+    Pattern(Regex),
+}
+
+impl PathMatcher {
+    pub fn compile(path: &PathMatch) -> Result<Self, String> {
+        match path {
+            PathMatch::Exact(path) => Ok(PathMatcher::Exact(path.clone())),
+            PathMatch::Prefix(prefix) => Ok(PathMatcher::Prefix(prefix.clone())),
+            PathMatch::Glob(glob) => Regex::new(&glob_to_regex(glob))
+                .map(PathMatcher::Pattern)
+                .map_err(|e| format!("Invalid glob '{}': {}", glob, e)),
+            PathMatch::Regex(regex) => Regex::new(regex)
+                .map(PathMatcher::Pattern)
+                .map_err(|e| format!("Invalid regex '{}': {}", regex, e)),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            PathMatcher::Exact(exact) => path == exact,
+            PathMatcher::Prefix(prefix) => {
+                path.starts_with(prefix.as_str())
+                    && (prefix.ends_with('/')
+                        || path.len() == prefix.len()
+                        || path.as_bytes()[prefix.len()] == b'/')
+            }
+            PathMatcher::Pattern(regex) => regex.is_match(path),
+        }
+    }
+}
+
+/// How specific a path match is, used to order the routes of a domain: exact paths first, then
+/// by length of the literal prefix, so `/v2/*` wins over `/`.
+pub fn specificity(path: Option<&PathMatch>) -> (u8, usize) {
+    match path {
+        Some(PathMatch::Exact(_)) => (3, 0),
+        Some(PathMatch::Prefix(prefix)) => (2, prefix.len()),
+        Some(PathMatch::Glob(glob)) => (2, glob.find(['*', '?']).unwrap_or(glob.len())),
+        Some(PathMatch::Regex(_)) => (1, 0),
+        None => (0, 0),
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    regex.push('$');
+    regex
+}
+
+/// Normalizes a request path before it is matched against routes, so that `//admin`,
+/// `/./admin` and `/%61dmin` are routed like `/admin`: escapes of unreserved characters are
+/// decoded (other escapes are kept, with uppercase hex digits), repeated slashes are merged and
+/// dot segments are resolved, never above the root.
+pub fn normalize(path: &str) -> Cow<'_, str> {
+    if !path.starts_with('/') || !(path.contains("//") || path.contains("/.") || path.contains('%'))
+    {
+        return Cow::Borrowed(path);
+    }
+
+    let decoded = decode_unreserved(path);
+    let segments: Vec<&str> = decoded[1..].split('/').collect();
+    let mut resolved: Vec<&str> = Vec::with_capacity(segments.len());
+    let mut trailing_slash = false;
+
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "" | "." => trailing_slash = last,
+            ".." => {
+                resolved.pop();
+                trailing_slash = last;
+            }
+            segment => resolved.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", resolved.join("/"));
+    if trailing_slash && !resolved.is_empty() {
+        normalized.push('/');
+    }
+
+    if normalized == path {
+        Cow::Borrowed(path)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+fn decode_unreserved(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = String::with_capacity(path.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escape = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+
+        match escape {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                decoded.push(byte as char);
+                i += 3;
+            }
+            Some(byte) => {
+                decoded.push_str(&format!("%{:02X}", byte));
+                i += 3;
+            }
+            None => {
+                let len = path[i..].chars().next().map_or(1, char::len_utf8);
+                decoded.push_str(&path[i..i + len]);
+                i += len;
+            }
+        }
+    }
+
+    decoded
+}
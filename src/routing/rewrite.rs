@@ -0,0 +1,159 @@
+use axum::http::{
+    header::{HeaderMap, LOCATION},
+    uri::{Authority, PathAndQuery, Uri},
+    HeaderValue,
+};
+use regex::Regex;
+
+use crate::env::state::RewriteConfig;
+
+/// A compiled `RewriteConfig`.
+#[derive(Debug)]
+pub struct Rewrite {
+    strip_prefix: Option<String>,
+    add_prefix: Option<String>,
+    regex: Option<(Regex, String)>,
+}
+
+impl Rewrite {
+    pub fn compile(config: &RewriteConfig) -> Result<Self, String> {
+        let regex = config
+            .regex
+            .as_deref()
+            .map(|regex| {
+                Regex::new(regex)
+                    .map(|compiled| (compiled, config.replacement.clone()))
+                    .map_err(|e| format!("Invalid rewrite regex '{}': {}", regex, e))
+            })
+            .transpose()?;
+
+        let prefix = |prefix: &Option<String>| {
+            prefix
+                .as_deref()
+                .map(|prefix| prefix.trim_end_matches('/'))
+                .filter(|prefix| !prefix.is_empty())
+                .map(String::from)
+        };
+
+        Ok(Self {
+            strip_prefix: prefix(&config.strip_prefix),
+            add_prefix: prefix(&config.add_prefix),
+            regex,
+        })
+    }
+
+    pub fn path(&self, path: &str) -> String {
+        let mut path = match &self.strip_prefix {
+            Some(prefix) => strip_segment_prefix(path, prefix)
+                .unwrap_or(path)
+                .to_string(),
+            None => path.to_string(),
+        };
+
+        if let Some((regex, replacement)) = &self.regex {
+            path = regex.replace(&path, replacement.as_str()).into_owned();
+        }
+
+        if !path.starts_with('/') {
+            path.insert(0, '/');
+        }
+
+        match &self.add_prefix {
+            Some(prefix) if path == "/" => format!("{}/", prefix),
+            Some(prefix) => format!("{}{}", prefix, path),
+            None => path,
+        }
+    }
+
+    /// Rewrites the path of `uri`, keeping the query.
+    pub fn uri(&self, uri: &Uri) -> Uri {
+        let path = self.path(uri.path());
+        let path_and_query = match uri.query() {
+            // A replacement may add query parameters of its own
+            Some(query) if path.contains('?') => format!("{}&{}", path, query),
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        let mut parts = uri.clone().into_parts();
+        match PathAndQuery::try_from(path_and_query) {
+            Ok(path_and_query) => parts.path_and_query = Some(path_and_query),
+            Err(_) => return uri.clone(),
+        }
+        Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+    }
+
+    /// Maps a `Location` path from the service back to the path the client used, undoing the
+    /// prefix changes, like nginx's `proxy_redirect`. Absolute URLs are only mapped when they
+    /// point at one of `authorities`, the service or the host the client asked for.
+    pub fn location(&self, location: &str, authorities: &[&str]) -> Option<String> {
+        if self.strip_prefix.is_none() && self.add_prefix.is_none() {
+            return None;
+        }
+
+        // Absolute URLs keep their scheme and authority, only the path is mapped
+        let path_start = match location.find("://") {
+            Some(scheme_end) => {
+                let start = scheme_end + 3;
+                let end = start + location[start..].find('/')?;
+                let default_port = match &location[..scheme_end] {
+                    scheme if scheme.eq_ignore_ascii_case("https") => 443,
+                    _ => 80,
+                };
+                if !authorities
+                    .iter()
+                    .any(|authority| same_authority(&location[start..end], authority, default_port))
+                {
+                    return None;
+                }
+                end
+            }
+            None if location.starts_with('/') => 0,
+            None => return None,
+        };
+        let (origin, path) = location.split_at(path_start);
+
+        let path = match &self.add_prefix {
+            Some(prefix) => strip_segment_prefix(path, prefix)?,
+            None => path,
+        };
+        let path = match &self.strip_prefix {
+            Some(prefix) if path.is_empty() || path == "/" => format!("{}/", prefix),
+            Some(prefix) if path.starts_with(['?', '#']) => format!("{}/{}", prefix, path),
+            Some(prefix) => format!("{}{}", prefix, path),
+            None if path.is_empty() => "/".to_string(),
+            None => path.to_string(),
+        };
+
+        Some(format!("{}{}", origin, path))
+    }
+
+    pub fn response_headers(&self, headers: &mut HeaderMap, authorities: &[&str]) {
+        let location = headers
+            .get(LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|location| self.location(location, authorities))
+            .and_then(|location| HeaderValue::from_str(&location).ok());
+
+        if let Some(location) = location {
+            headers.insert(LOCATION, location);
+        }
+    }
+}
+
+/// Strips `prefix` from `path` on a segment boundary, leaving the remainder with its separator.
+fn strip_segment_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with(['/', '?', '#'])).then_some(rest)
+}
+
+/// Compares two authorities by host and port, a missing port meaning `default_port`.
+fn same_authority(a: &str, b: &str, default_port: u16) -> bool {
+    match (a.parse::<Authority>(), b.parse::<Authority>()) {
+        (Ok(a), Ok(b)) => {
+            a.host().eq_ignore_ascii_case(b.host())
+                && a.port_u16().unwrap_or(default_port) == b.port_u16().unwrap_or(default_port)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,51 @@
+#[cfg(test)]
+mod tests {
+    use rcgen::{CertificateParams, DnType, KeyPair};
+    use rustls::pki_types::CertificateDer;
+
+    use crate::env::state::ClientAuthConfig;
+    use crate::server::client_cert::ClientCertificate;
+
+    fn certificate() -> ClientCertificate {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "ops-alice");
+        params
+            .distinguished_name
+            .push(DnType::OrganizationName, "Corp");
+        params.distinguished_name.push(DnType::CountryName, "US");
+        let cert = params.self_signed(&KeyPair::generate().unwrap()).unwrap();
+
+        ClientCertificate::from_der(&CertificateDer::from(cert.der().to_vec())).unwrap()
+    }
+
+    fn allows(cert: &ClientCertificate, pattern: &str) -> bool {
+        let config: ClientAuthConfig =
+            serde_yaml::from_str(&format!("allowed_subjects: ['{}']", pattern)).unwrap();
+        cert.is_allowed(&config)
+    }
+
+    #[test]
+    fn should_match_subject_patterns_against_single_rdns() {
+        let cert = certificate();
+        assert_eq!(cert.subject, "CN=ops-alice, O=Corp, C=US");
+        assert_eq!(cert.subject_rdns, ["CN=ops-alice", "O=Corp", "C=US"]);
+
+        assert!(allows(&cert, "CN=ops-*"));
+        assert!(allows(&cert, "O=Corp"));
+        assert!(!allows(&cert, "CN=admin"));
+        // `*` stays within the RDN
+        assert!(!allows(&cert, "CN=*Corp"));
+        assert!(!allows(&cert, "CN=ops-*US"));
+    }
+
+    #[test]
+    fn should_match_multi_rdn_patterns_against_the_whole_subject() {
+        let cert = certificate();
+
+        assert!(allows(&cert, "CN=ops-alice, O=Corp, C=US"));
+        assert!(allows(&cert, "CN=ops-*, C=US"));
+        assert!(!allows(&cert, "O=Corp, CN=ops-alice"));
+    }
+}
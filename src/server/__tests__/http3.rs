@@ -0,0 +1,190 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::poll_fn,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use axum::{
+        body::Body,
+        extract::Request,
+        http::{header::ALT_SVC, HeaderMap, HeaderValue, StatusCode, Version},
+        middleware::from_fn_with_state,
+        response::Response,
+        Router,
+    };
+    use bytes::{Buf, Bytes};
+    use http_body_util::{BodyExt, Full};
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+
+    use crate::env::state::{AppState, Config};
+    use crate::routes::proxy::proxy_handler;
+    use crate::server::{http3, shutdown::Shutdown};
+
+    /// Writes a self-signed certificate for `localhost`, returning the certificate and key paths.
+    fn self_signed(name: &str) -> (PathBuf, PathBuf) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("traffic-switcher-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let (cert, key) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert, certified.cert.pem()).unwrap();
+        std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+        (cert, key)
+    }
+
+    /// h2c upstream echoing the request body, and the `x-request` trailer as `x-echoed`.
+    async fn echo_upstream() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(|req: Request| async move {
+            let collected = req.into_body().collect().await.unwrap();
+            let mut trailers = HeaderMap::new();
+            if let Some(value) = collected.trailers().and_then(|t| t.get("x-request")) {
+                trailers.insert("x-echoed", value.clone());
+            }
+            let body = Full::new(collected.to_bytes())
+                .with_trailers(async move { Some(Ok::<_, Infallible>(trailers)) });
+            Response::new(Body::new(body))
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    fn state(upstream_port: u16, cert: &Path, key: &Path) -> AppState {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+tls:
+    port: 0
+    cert: {cert}
+    key: {key}
+    client_ca: {cert}
+    http3: {{}}
+routes:
+    - domain: localhost
+      type: service
+      service: app
+    - domain: secure.localhost
+      type: service
+      service: app
+      client_auth:
+          mode: required
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {port}
+      protocol: h2c
+",
+            cert = cert.display(),
+            key = key.display(),
+            port = upstream_port,
+        ))
+        .unwrap();
+        AppState::new(config)
+    }
+
+    async fn client(addr: SocketAddr, ca: &Path) -> quinn::Connection {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in crate::utils::pem::load_certs(ca.to_str().unwrap()).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+
+        let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+            quinn::crypto::rustls::QuicClientConfig::try_from(tls).unwrap(),
+        )));
+        endpoint.connect(addr, "localhost").unwrap().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_proxy_requests_over_http3() {
+        let (cert, key) = self_signed("http3");
+        let state = state(echo_upstream().await, &cert, &key);
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server_tls = state.server_tls.read().await.clone().unwrap();
+        let endpoint = http3::endpoint(socket, &server_tls).unwrap();
+        let app = Router::new()
+            .fallback(proxy_handler)
+            .with_state(state.clone());
+        tokio::spawn(http3::serve(endpoint, state, app, Shutdown::new()));
+
+        let connection = client(addr, &cert).await;
+        let (mut driver, mut sender) = h3::client::new(h3_quinn::Connection::new(connection))
+            .await
+            .unwrap();
+        tokio::spawn(async move { poll_fn(|cx| driver.poll_close(cx)).await });
+
+        let req = Request::post("https://localhost/echo").body(()).unwrap();
+        let mut stream = sender.send_request(req).await.unwrap();
+        stream.send_data(Bytes::from("hello")).await.unwrap();
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-request", HeaderValue::from_static("sent"));
+        stream.send_trailers(trailers).await.unwrap();
+        stream.finish().await.unwrap();
+
+        let response = stream.recv_response().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_3);
+        assert!(response.headers().get(ALT_SVC).is_none());
+
+        let mut body = Vec::new();
+        while let Some(mut chunk) = stream.recv_data().await.unwrap() {
+            body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+        }
+        assert_eq!(body, b"hello");
+
+        let trailers = stream.recv_trailers().await.unwrap().unwrap();
+        assert_eq!(trailers["x-echoed"], "sent");
+    }
+
+    #[tokio::test]
+    async fn should_not_advertise_http3_for_client_auth_domains() {
+        let (cert, key) = self_signed("alt-svc");
+        let state = state(0, &cert, &key);
+        let value = HeaderValue::from_static("h3=\":443\"; ma=86400");
+        let app = Router::new()
+            .fallback(|| async { StatusCode::OK })
+            .layer(from_fn_with_state((value, state), http3::alt_svc));
+
+        let alt_svc = |host: &'static str, version: Version| {
+            let app = app.clone();
+            async move {
+                let req = Request::get("/")
+                    .version(version)
+                    .header("Host", host)
+                    .body(Body::empty())
+                    .unwrap();
+                app.oneshot(req)
+                    .await
+                    .unwrap()
+                    .headers()
+                    .get(ALT_SVC)
+                    .cloned()
+            }
+        };
+
+        assert!(alt_svc("localhost", Version::HTTP_11).await.is_some());
+        assert!(alt_svc("localhost:8443", Version::HTTP_2).await.is_some());
+        assert!(alt_svc("secure.localhost", Version::HTTP_11)
+            .await
+            .is_none());
+        assert!(alt_svc("localhost", Version::HTTP_3).await.is_none());
+    }
+}
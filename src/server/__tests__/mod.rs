@@ -0,0 +1,5 @@
+mod client_cert;
+mod http3;
+mod shutdown;
+#[cfg(unix)]
+mod upgrade;
@@ -0,0 +1,123 @@
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{body::Body, extract::Request, http::StatusCode, Router};
+    use http_body_util::BodyExt;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::Notify,
+    };
+    use tower::ServiceExt;
+
+    use crate::env::state::{AppState, Config, Http2Config};
+    use crate::routes::{app::app, proxy::proxy_handler};
+    use crate::server;
+
+    /// Upstream holding every request until `release` is notified.
+    async fn held_upstream(release: Arc<Notify>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(move || {
+            let release = release.clone();
+            async move {
+                release.notified().await;
+                "done"
+            }
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    fn state(upstream_port: u16) -> AppState {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: app
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+",
+            upstream_port
+        ))
+        .unwrap();
+        AppState::new(config)
+    }
+
+    async fn ready(state: &AppState) -> (StatusCode, serde_json::Value) {
+        let req = Request::get("/ready").body(Body::empty()).unwrap();
+        let response = app().with_state(state.clone()).oneshot(req).await.unwrap();
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn should_fail_readiness_once_the_shutdown_starts() {
+        let state = state(1);
+
+        let (status, body) = ready(&state).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        state.shutdown.trigger();
+        let (status, body) = ready(&state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "shutting_down");
+    }
+
+    #[tokio::test]
+    async fn should_close_connections_left_after_the_grace_period() {
+        let release = Arc::new(Notify::new());
+        let upstream_port = held_upstream(release.clone()).await;
+        let state = state(upstream_port);
+        let shutdown = state.shutdown.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(server::serve(
+            listener,
+            Router::new()
+                .fallback(proxy_handler)
+                .with_state(state.clone()),
+            server::connection_builder(&Http2Config::default()),
+            None,
+            shutdown.clone(),
+        ));
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .await
+            .unwrap();
+        let upstream = [format!("127.0.0.1:{}", upstream_port)];
+        while state.active_connections.counts(&upstream).requests == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        shutdown.trigger();
+        server.await.unwrap();
+        assert!(TcpStream::connect(addr).await.is_err());
+
+        // The request outlives the grace period, so its connection is closed without a response
+        assert_eq!(
+            shutdown
+                .wait_for_connections(Duration::from_millis(200))
+                .await,
+            1
+        );
+        let mut response = Vec::new();
+        let _ = client.read_to_end(&mut response).await;
+        assert!(response.is_empty());
+        assert_eq!(
+            shutdown.wait_for_connections(Duration::from_secs(1)).await,
+            0
+        );
+    }
+}
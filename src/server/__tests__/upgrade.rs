@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        os::fd::{IntoRawFd, RawFd},
+        time::Duration,
+    };
+
+    use crate::env::state::Config;
+    use crate::server::{
+        listeners::{Inherited, Listeners},
+        upgrade::{handover_env, ready_pipe, wait_ready, write_state, Handover},
+    };
+
+    fn config() -> Config {
+        serde_yaml::from_str(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: app
+      host: 127.0.0.1
+      port: 3000
+",
+        )
+        .unwrap()
+    }
+
+    fn bind() -> (SocketAddr, RawFd) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        (listener.local_addr().unwrap(), listener.into_raw_fd())
+    }
+
+    #[tokio::test]
+    async fn should_hand_listeners_and_ports_over_through_the_environment() {
+        let (api_addr, api_fd) = bind();
+        let (proxy_addr, proxy_fd) = bind();
+
+        let mut switched = config();
+        switched.services[0].port = 4000;
+        switched.services[0].previous_port = Some(3000);
+        let state_file = std::env::temp_dir().join(format!(
+            "traffic-switcher-handover-{}.json",
+            std::process::id()
+        ));
+        let state_file = state_file.to_str().unwrap();
+        write_state(&switched, state_file).await.unwrap();
+
+        let (ready_read, ready_write) = ready_pipe().unwrap();
+        let fds = [("api".to_string(), api_fd), ("proxy".to_string(), proxy_fd)];
+        let env = handover_env(&fds, state_file, ready_write.into_raw_fd());
+        // No other test touches these variables
+        for (key, value) in &env {
+            std::env::set_var(key, value);
+        }
+
+        let inherited = Inherited::take();
+        let handover = Handover::take();
+        for (key, _) in &env {
+            assert!(std::env::var(key).is_err(), "{} was left set", key);
+        }
+
+        let listeners = Listeners::open(inherited, api_addr, proxy_addr, None, &[], None, &[])
+            .await
+            .unwrap();
+        assert_eq!(listeners.api.local_addr().unwrap(), api_addr);
+        assert_eq!(listeners.proxy.local_addr().unwrap(), proxy_addr);
+
+        let mut restored = config();
+        handover.restore_state(&mut restored).await;
+        assert_eq!(restored.services[0].port, 4000);
+        assert_eq!(restored.services[0].previous_port, Some(3000));
+        assert!(!std::path::Path::new(state_file).exists());
+
+        handover.notify_ready();
+        assert_eq!(wait_ready(ready_read, Duration::from_secs(1)).await, Ok(()));
+    }
+}
@@ -0,0 +1,155 @@
+use std::net::IpAddr;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use rustls::pki_types::CertificateDer;
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::GeneralName,
+    objects::{oid2abbrev, oid_registry},
+    prelude::FromDer,
+    x509::X509Name,
+};
+
+use crate::{env::state::ClientAuthConfig, utils::pattern::wildcard_match};
+
+const SUBJECT_HEADER: &str = "x-client-cert-subject";
+const ISSUER_HEADER: &str = "x-client-cert-issuer";
+const SERIAL_HEADER: &str = "x-client-cert-serial";
+const SAN_HEADER: &str = "x-client-cert-san";
+const VERIFY_HEADER: &str = "x-client-cert-verify";
+
+/// Identity of a client certificate that was verified during the TLS handshake.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate {
+    /// Full distinguished name, e.g. `CN=ops-alice, O=Corp`
+    pub subject: String,
+    /// Each relative distinguished name of the subject on its own, e.g. `CN=ops-alice`
+    pub subject_rdns: Vec<String>,
+    pub issuer: String,
+    pub serial: String,
+    pub sans: Vec<String>,
+}
+
+impl ClientCertificate {
+    pub fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der.as_ref()).ok()?;
+
+        let sans = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(value)
+                        | GeneralName::URI(value)
+                        | GeneralName::RFC822Name(value) => Some(value.to_string()),
+                        GeneralName::IPAddress(bytes) => ip_from_bytes(bytes),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            subject_rdns: rdns(cert.subject()),
+            issuer: cert.issuer().to_string(),
+            serial: cert.raw_serial_as_string(),
+            sans,
+        })
+    }
+
+    /// A certificate is allowed when no patterns are configured, or when its subject or any of
+    /// its SANs matches one of them.
+    pub fn is_allowed(&self, config: &ClientAuthConfig) -> bool {
+        if config.allowed_subjects.is_empty() && config.allowed_sans.is_empty() {
+            return true;
+        }
+
+        config
+            .allowed_subjects
+            .iter()
+            .any(|pattern| self.subject_matches(pattern))
+            || config
+                .allowed_sans
+                .iter()
+                .any(|pattern| self.sans.iter().any(|san| wildcard_match(pattern, san)))
+    }
+
+    /// A pattern naming several RDNs, separated by `, `, is matched against the whole subject.
+    /// Otherwise it has to match one of its RDNs, so `*` can't run from one RDN into the next.
+    pub fn subject_matches(&self, pattern: &str) -> bool {
+        if pattern.contains(", ") {
+            wildcard_match(pattern, &self.subject)
+        } else {
+            self.subject_rdns
+                .iter()
+                .any(|rdn| wildcard_match(pattern, rdn))
+        }
+    }
+
+    pub fn insert_headers(&self, headers: &mut HeaderMap) {
+        let values = [
+            (SUBJECT_HEADER, self.subject.clone()),
+            (ISSUER_HEADER, self.issuer.clone()),
+            (SERIAL_HEADER, self.serial.clone()),
+            (SAN_HEADER, self.sans.join(", ")),
+            (VERIFY_HEADER, "SUCCESS".to_string()),
+        ];
+
+        for (name, value) in values {
+            if let Ok(value) = HeaderValue::from_str(&value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        }
+    }
+
+    pub fn insert_missing_header(headers: &mut HeaderMap) {
+        headers.insert(VERIFY_HEADER, HeaderValue::from_static("NONE"));
+    }
+
+    /// Drops identity headers sent by the client so they cannot be spoofed.
+    pub fn remove_headers(headers: &mut HeaderMap) {
+        for name in [
+            SUBJECT_HEADER,
+            ISSUER_HEADER,
+            SERIAL_HEADER,
+            SAN_HEADER,
+            VERIFY_HEADER,
+        ] {
+            headers.remove(name);
+        }
+    }
+}
+
+fn ip_from_bytes(bytes: &[u8]) -> Option<String> {
+    let ip = match bytes.len() {
+        4 => IpAddr::from(<[u8; 4]>::try_from(bytes).ok()?),
+        16 => IpAddr::from(<[u8; 16]>::try_from(bytes).ok()?),
+        _ => return None,
+    };
+
+    Some(ip.to_string())
+}
+
+/// Formats every RDN the way the whole name is formatted, attributes of a multi-valued RDN
+/// joined with ` + `.
+fn rdns(name: &X509Name<'_>) -> Vec<String> {
+    name.iter()
+        .map(|rdn| {
+            rdn.iter()
+                .map(|attr| {
+                    let kind = oid2abbrev(attr.attr_type(), oid_registry())
+                        .map(String::from)
+                        .unwrap_or_else(|_| attr.attr_type().to_id_string());
+                    let value = attr.as_str().map(String::from).unwrap_or_default();
+                    format!("{}={}", kind, value)
+                })
+                .collect::<Vec<_>>()
+                .join(" + ")
+        })
+        .collect()
+}
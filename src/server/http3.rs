@@ -0,0 +1,306 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{ready, Context, Poll},
+};
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{ALT_SVC, HOST},
+        HeaderMap, HeaderValue, Response, Version,
+    },
+    middleware::Next,
+    Router,
+};
+use bytes::{Buf, Bytes};
+use h3::{
+    error::StreamError,
+    server::{RequestResolver, RequestStream},
+};
+use http_body_util::BodyExt;
+use hyper::body::Frame;
+use quinn::{crypto::rustls::QuicServerConfig, Endpoint, EndpointConfig, Incoming, TokioRuntime};
+use tower::ServiceExt;
+use tracing::{debug, error, warn};
+
+use super::{shutdown::Shutdown, tls::ServerTls};
+use crate::{env::state::AppState, metrics::Metrics, proxy_protocol::Addresses};
+
+/// Runs QUIC on `socket`, the HTTP/3 socket of the listeners.
+pub fn endpoint(socket: std::net::UdpSocket, tls: &ServerTls) -> io::Result<Endpoint> {
+    Endpoint::new(
+        EndpointConfig::default(),
+        Some(server_config(tls)?),
+        socket,
+        Arc::new(TokioRuntime),
+    )
+}
+
+fn server_config(tls: &ServerTls) -> io::Result<quinn::ServerConfig> {
+    let crypto = QuicServerConfig::try_from(tls.quic_config()).map_err(io::Error::other)?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(crypto)))
+}
+
+/// Accepts QUIC connections on `endpoint` and serves `app` over HTTP/3 until the shutdown is
+/// triggered. Certificates reloaded with the configuration apply to new connections.
+pub async fn serve(endpoint: Endpoint, state: AppState, app: Router, shutdown: Shutdown) {
+    let mut current: Option<(Arc<ServerTls>, Arc<quinn::ServerConfig>)> = None;
+    let local_addr = match endpoint.local_addr() {
+        Ok(local_addr) => local_addr,
+        Err(e) => {
+            error!("Failed to read the address of the HTTP/3 socket: {}", e);
+            return;
+        }
+    };
+
+    loop {
+        let incoming = tokio::select! {
+            incoming = endpoint.accept() => match incoming {
+                Some(incoming) => incoming,
+                None => break,
+            },
+            _ = shutdown.triggered() => break,
+        };
+
+        let Some(tls) = state.server_tls.read().await.clone() else {
+            incoming.refuse();
+            continue;
+        };
+        let config = match &current {
+            Some((built_from, config)) if Arc::ptr_eq(built_from, &tls) => config.clone(),
+            _ => match server_config(&tls).map(Arc::new) {
+                Ok(config) => {
+                    current = Some((tls, config.clone()));
+                    config
+                }
+                Err(e) => {
+                    warn!("Failed to build the HTTP/3 TLS configuration: {}", e);
+                    incoming.refuse();
+                    continue;
+                }
+            },
+        };
+
+        tokio::spawn(serve_connection(
+            incoming,
+            config,
+            local_addr,
+            app.clone(),
+            state.metrics.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    // Refuse new connections while the open ones finish
+    endpoint.set_server_config(None);
+}
+
+async fn serve_connection(
+    incoming: Incoming,
+    config: Arc<quinn::ServerConfig>,
+    local_addr: SocketAddr,
+    app: Router,
+    metrics: Arc<Metrics>,
+    shutdown: Shutdown,
+) {
+    // Shared with the request streams, which may outlive the accept loop
+    let connection_guard = Arc::new(shutdown.connection());
+    let remote_addr = incoming.remote_address();
+    let addresses = Addresses {
+        source: remote_addr,
+        destination: SocketAddr::new(
+            incoming.local_ip().unwrap_or(local_addr.ip()),
+            local_addr.port(),
+        ),
+    };
+
+    let connecting = incoming.accept_with(config);
+    let connection = match async { connecting?.await }.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            debug!("QUIC handshake with {} failed: {}", remote_addr, e);
+            metrics
+                .http3_connections_total
+                .with_label_values(&["failed"])
+                .inc();
+            return;
+        }
+    };
+    metrics
+        .http3_connections_total
+        .with_label_values(&["accepted"])
+        .inc();
+    let _active = metrics.http3_connection();
+
+    let mut h3 = match h3::server::Connection::new(h3_quinn::Connection::new(connection)).await {
+        Ok(h3) => h3,
+        Err(e) => {
+            debug!("HTTP/3 setup with {} failed: {}", remote_addr, e);
+            return;
+        }
+    };
+
+    let mut closing = false;
+    loop {
+        tokio::select! {
+            accepted = h3.accept() => match accepted {
+                Ok(Some(resolver)) => {
+                    let app = app.clone();
+                    let metrics = metrics.clone();
+                    let connection_guard = connection_guard.clone();
+
+                    tokio::spawn(async move {
+                        let _connection = connection_guard;
+                        let result = serve_request(resolver, app, addresses).await;
+                        let label = if result.is_ok() { "completed" } else { "failed" };
+                        if let Err(e) = result {
+                            debug!("HTTP/3 request from {} failed: {}", remote_addr, e);
+                        }
+                        metrics.http3_requests_total.with_label_values(&[label]).inc();
+                    });
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("HTTP/3 connection from {} closed with error: {}", remote_addr, e);
+                    break;
+                }
+            },
+            _ = shutdown.triggered(), if !closing => {
+                closing = true;
+                // GOAWAY: streams already accepted are completed, new ones refused
+                if let Err(e) = h3.shutdown(0).await {
+                    debug!("HTTP/3 shutdown of {} failed: {}", remote_addr, e);
+                }
+            }
+        }
+    }
+}
+
+async fn serve_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    app: Router,
+    addresses: Addresses,
+) -> Result<(), StreamError> {
+    let (req, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+
+    let mut req = req.map(|()| Body::new(RequestBody::new(recv)));
+    req.extensions_mut().insert(ConnectInfo(addresses.source));
+    req.extensions_mut().insert(addresses);
+
+    let response = match app.oneshot(req).await {
+        Ok(response) => response,
+        Err(infallible) => match infallible {},
+    };
+    let (parts, mut body) = response.into_parts();
+    send.send_response(Response::from_parts(parts, ())).await?;
+
+    while let Some(frame) = body.frame().await {
+        let Ok(frame) = frame else {
+            // The response body failed mid-stream, e.g. an upstream timeout
+            send.stop_stream(h3::error::Code::H3_INTERNAL_ERROR);
+            return Ok(());
+        };
+
+        match frame.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+
+    send.finish().await
+}
+
+/// The body of an HTTP/3 request, data frames followed by optional trailers.
+struct RequestBody {
+    stream: RequestStream<h3_quinn::RecvStream, Bytes>,
+    data_done: bool,
+    finished: bool,
+}
+
+impl RequestBody {
+    fn new(stream: RequestStream<h3_quinn::RecvStream, Bytes>) -> Self {
+        Self {
+            stream,
+            data_done: false,
+            finished: false,
+        }
+    }
+}
+
+impl HttpBody for RequestBody {
+    type Data = Bytes;
+    type Error = StreamError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if !this.data_done {
+            match ready!(this.stream.poll_recv_data(cx)) {
+                Ok(Some(mut data)) => {
+                    let data = data.copy_to_bytes(data.remaining());
+                    return Poll::Ready(Some(Ok(Frame::data(data))));
+                }
+                Ok(None) => this.data_done = true,
+                Err(e) => return Poll::Ready(Some(Err(e))),
+            }
+        }
+
+        if this.finished {
+            return Poll::Ready(None);
+        }
+
+        let trailers: Option<HeaderMap> = match ready!(this.stream.poll_recv_trailers(cx)) {
+            Ok(trailers) => trailers,
+            Err(e) => return Poll::Ready(Some(Err(e))),
+        };
+        this.finished = true;
+        Poll::Ready(trailers.map(|trailers| Ok(Frame::trailers(trailers))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished
+    }
+}
+
+/// Advertises the HTTP/3 endpoint on responses sent over HTTP/1 and HTTP/2, except for domains
+/// whose routes ask for client certificates, which QUIC does not request.
+pub async fn alt_svc(
+    State((value, state)): State<(HeaderValue, AppState)>,
+    req: Request,
+    next: Next,
+) -> Response<Body> {
+    let host = req
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| req.uri().host())
+        .map(|host| host.split(':').next().unwrap_or(host).to_string());
+    let advertise = req.version() != Version::HTTP_3
+        && match host {
+            Some(host) => state
+                .route_table
+                .read()
+                .await
+                .client_auth_mode(&host)
+                .is_none(),
+            None => true,
+        };
+    let mut response = next.run(req).await;
+
+    if advertise {
+        response.headers_mut().entry(ALT_SVC).or_insert(value);
+    }
+    response
+}
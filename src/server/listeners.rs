@@ -0,0 +1,228 @@
+use std::{collections::HashMap, io, net::SocketAddr};
+
+use tokio::net::{TcpListener, UdpSocket};
+use tracing::{info, warn};
+
+/// Environment variable naming the listening sockets handed over by a previous process, as
+/// `api:<fd>,proxy:<fd>,tls:<fd>`, with TCP listeners named `tcp.<name>`, UDP listeners
+/// `udp.<name>` and the QUIC socket `http3`.
+pub const LISTEN_FDS_ENV: &str = "TRAFFIC_SWITCHER_LISTEN_FDS";
+
+#[cfg(unix)]
+type OwnedSocket = std::os::fd::OwnedFd;
+#[cfg(windows)]
+type OwnedSocket = std::os::windows::io::OwnedSocket;
+
+pub struct Listeners {
+    pub api: TcpListener,
+    pub proxy: TcpListener,
+    pub tls: Option<TcpListener>,
+    /// Raw TCP listeners by name
+    pub tcp: Vec<(String, TcpListener)>,
+    /// Socket of the HTTP/3 listener, handed to QUIC as it is
+    pub http3: Option<std::net::UdpSocket>,
+    /// UDP listeners by name
+    pub udp: Vec<(String, UdpSocket)>,
+}
+
+/// Sockets inherited from a previous process or from systemd socket activation, by name.
+pub struct Inherited(HashMap<String, OwnedSocket>);
+
+impl Inherited {
+    /// Takes ownership of the inherited sockets and clears the variables describing them, so
+    /// child processes don't see them. Must run before any other thread starts, since changing
+    /// the environment is not thread-safe.
+    pub fn take() -> Self {
+        Self(inherited())
+    }
+}
+
+impl Listeners {
+    /// Takes over the `inherited` sockets, binding the ones that were not passed in.
+    pub async fn open(
+        Inherited(mut inherited): Inherited,
+        api_addr: SocketAddr,
+        proxy_addr: SocketAddr,
+        tls_addr: Option<SocketAddr>,
+        tcp_addrs: &[(String, SocketAddr)],
+        http3_addr: Option<SocketAddr>,
+        udp_addrs: &[(String, SocketAddr)],
+    ) -> io::Result<Self> {
+        let api = listener(&mut inherited, "api", api_addr).await?;
+        let proxy = listener(&mut inherited, "proxy", proxy_addr).await?;
+        let tls = match tls_addr {
+            Some(tls_addr) => Some(listener(&mut inherited, "tls", tls_addr).await?),
+            None => None,
+        };
+        let mut tcp = Vec::new();
+        for (name, addr) in tcp_addrs {
+            let listener = listener(&mut inherited, &format!("tcp.{}", name), *addr).await?;
+            tcp.push((name.clone(), listener));
+        }
+        let http3 = match http3_addr {
+            Some(http3_addr) => Some(udp_socket(&mut inherited, "http3", http3_addr)?),
+            None => None,
+        };
+        let mut udp = Vec::new();
+        for (name, addr) in udp_addrs {
+            let socket = udp_socket(&mut inherited, &format!("udp.{}", name), *addr)?;
+            udp.push((name.clone(), UdpSocket::from_std(socket)?));
+        }
+
+        for name in inherited.keys() {
+            warn!(
+                "Ignoring inherited listener '{}' that is not configured",
+                name
+            );
+        }
+
+        Ok(Self {
+            api,
+            proxy,
+            tls,
+            tcp,
+            http3,
+            udp,
+        })
+    }
+
+    /// Raw descriptors of the listeners, to hand them over to a new process.
+    #[cfg(unix)]
+    pub fn fds(&self) -> Vec<(String, std::os::fd::RawFd)> {
+        use std::os::fd::AsRawFd;
+
+        let mut fds = vec![
+            ("api".to_string(), self.api.as_raw_fd()),
+            ("proxy".to_string(), self.proxy.as_raw_fd()),
+        ];
+        if let Some(tls) = &self.tls {
+            fds.push(("tls".to_string(), tls.as_raw_fd()));
+        }
+        for (name, listener) in &self.tcp {
+            fds.push((format!("tcp.{}", name), listener.as_raw_fd()));
+        }
+        if let Some(http3) = &self.http3 {
+            fds.push(("http3".to_string(), http3.as_raw_fd()));
+        }
+        for (name, socket) in &self.udp {
+            fds.push((format!("udp.{}", name), socket.as_raw_fd()));
+        }
+        fds
+    }
+}
+
+async fn listener(
+    inherited: &mut HashMap<String, OwnedSocket>,
+    name: &str,
+    addr: SocketAddr,
+) -> io::Result<TcpListener> {
+    if let Some(fd) = inherited.remove(name) {
+        let listener = std::net::TcpListener::from(fd);
+        let local_addr = listener.local_addr()?;
+
+        if local_addr.port() == addr.port() {
+            info!("Using inherited {} listener on {}", name, local_addr);
+            listener.set_nonblocking(true)?;
+            return TcpListener::from_std(listener);
+        }
+
+        warn!(
+            "Inherited {} listener is on {} but {} is configured, binding a new one",
+            name, local_addr, addr
+        );
+    }
+
+    TcpListener::bind(addr).await
+}
+
+fn udp_socket(
+    inherited: &mut HashMap<String, OwnedSocket>,
+    name: &str,
+    addr: SocketAddr,
+) -> io::Result<std::net::UdpSocket> {
+    if let Some(fd) = inherited.remove(name) {
+        let socket = std::net::UdpSocket::from(fd);
+        let local_addr = socket.local_addr()?;
+
+        if local_addr.port() == addr.port() {
+            info!("Using inherited {} socket on {}", name, local_addr);
+            socket.set_nonblocking(true)?;
+            return Ok(socket);
+        }
+
+        warn!(
+            "Inherited {} socket is on {} but {} is configured, binding a new one",
+            name, local_addr, addr
+        );
+    }
+
+    let socket = std::net::UdpSocket::bind(addr)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+#[cfg(unix)]
+fn inherited() -> HashMap<String, OwnedSocket> {
+    use std::os::fd::{FromRawFd, RawFd};
+
+    const SD_LISTEN_FDS_START: RawFd = 3;
+
+    let handed_over = std::env::var(LISTEN_FDS_ENV).ok().map(|fds| {
+        fds.split(',')
+            .filter_map(|entry| {
+                let (name, fd) = entry.split_once(':')?;
+                Some((name.to_string(), fd.parse::<RawFd>().ok()?))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    // systemd socket activation, with sockets named after the listener through `FileDescriptorName=`
+    // or given in the order api, proxy, tls
+    let activated = || {
+        let pid = std::env::var("LISTEN_PID").ok()?.parse::<u32>().ok()?;
+        if pid != std::process::id() {
+            return None;
+        }
+
+        let count = std::env::var("LISTEN_FDS").ok()?.parse::<RawFd>().ok()?;
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        let mut names = names.split(':');
+        let defaults = ["api", "proxy", "tls"];
+
+        Some(
+            (0..count)
+                .map(|i| {
+                    let name = names
+                        .next()
+                        .filter(|name| !name.is_empty())
+                        .or_else(|| defaults.get(i as usize).copied())
+                        .unwrap_or("unknown");
+                    (name.to_string(), SD_LISTEN_FDS_START + i)
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    let fds = handed_over.or_else(activated).unwrap_or_default();
+
+    for key in [LISTEN_FDS_ENV, "LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(key);
+    }
+
+    fds.into_iter()
+        .map(|(name, fd)| {
+            // SAFETY: the descriptor was passed to this process to be owned by it; marking it
+            // close-on-exec again keeps it out of later child processes
+            let fd = unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                OwnedSocket::from_raw_fd(fd)
+            };
+            (name, fd)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn inherited() -> HashMap<String, OwnedSocket> {
+    HashMap::new()
+}
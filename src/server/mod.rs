@@ -0,0 +1,207 @@
+mod __tests__;
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, Router};
+use hyper::{body::Incoming, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tower::ServiceExt;
+use tracing::{debug, error};
+
+use self::{client_cert::ClientCertificate, shutdown::Shutdown};
+use crate::{
+    env::state::{AppState, Http2Config, ProxyProtocolConfig},
+    proxy_protocol::{self, Addresses},
+};
+
+pub mod client_cert;
+pub mod http3;
+pub mod listeners;
+pub mod shutdown;
+pub mod tls;
+#[cfg(unix)]
+pub mod upgrade;
+
+/// Pause after a failed accept, so a listener out of file descriptors doesn't spin.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Connection settings shared by all listeners: HTTP/1, plus HTTP/2 unless it is disabled.
+pub fn connection_builder(http2: &Http2Config) -> Builder<TokioExecutor> {
+    let mut builder = Builder::new(TokioExecutor::new());
+
+    if !http2.enabled {
+        return builder.http1_only();
+    }
+
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(http2.max_concurrent_streams)
+        .initial_stream_window_size(http2.initial_stream_window_size)
+        .initial_connection_window_size(http2.initial_connection_window_size)
+        .keep_alive_interval(http2.keep_alive_interval_seconds.map(Duration::from_secs))
+        .keep_alive_timeout(Duration::from_secs(http2.keep_alive_timeout_seconds));
+    builder
+}
+
+/// Serves `app` over plain HTTP on `listener` until the shutdown is triggered.
+pub async fn serve(
+    listener: TcpListener,
+    app: Router,
+    builder: Builder<TokioExecutor>,
+    proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+    shutdown: Shutdown,
+) {
+    while let Some((stream, remote_addr)) = accept(&listener, &shutdown).await {
+        let app = app.clone();
+        let builder = builder.clone();
+        let proxy_protocol = proxy_protocol.clone();
+        let shutdown = shutdown.clone();
+
+        tokio::spawn(async move {
+            let (stream, addresses) =
+                match proxy_protocol::accept(stream, proxy_protocol.as_deref()).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Closing the connection from {}: {}", remote_addr, e);
+                        return;
+                    }
+                };
+
+            serve_connection(stream, addresses, None, app, builder, shutdown).await;
+        });
+    }
+}
+
+/// Accepts TLS connections on `listener` and serves `app` on each of them until the shutdown
+/// is triggered.
+pub async fn serve_tls(
+    listener: TcpListener,
+    state: AppState,
+    app: Router,
+    builder: Builder<TokioExecutor>,
+    proxy_protocol: Option<Arc<ProxyProtocolConfig>>,
+    shutdown: Shutdown,
+) {
+    while let Some((stream, remote_addr)) = accept(&listener, &shutdown).await {
+        let state = state.clone();
+        let app = app.clone();
+        let builder = builder.clone();
+        let proxy_protocol = proxy_protocol.clone();
+        let shutdown = shutdown.clone();
+
+        tokio::spawn(async move {
+            let (stream, addresses) =
+                match proxy_protocol::accept(stream, proxy_protocol.as_deref()).await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        debug!("Closing the connection from {}: {}", remote_addr, e);
+                        return;
+                    }
+                };
+            let (stream, client_cert) = match tls::accept(&state, stream).await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("TLS handshake with {} failed: {}", addresses.source, e);
+                    return;
+                }
+            };
+
+            serve_connection(stream, addresses, client_cert, app, builder, shutdown).await;
+        });
+    }
+}
+
+/// Accepts the next connection on `listener`, or returns `None` once the shutdown is triggered.
+/// Errors of a single connection are skipped, others such as running out of file descriptors
+/// are retried after a pause, like `axum::serve` does.
+pub async fn accept(
+    listener: &TcpListener,
+    shutdown: &Shutdown,
+) -> Option<(TcpStream, SocketAddr)> {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => return Some(accepted),
+                Err(e) if is_connection_error(&e) => {
+                    debug!("Failed to accept connection: {}", e);
+                    continue;
+                }
+                Err(e) => error!("Failed to accept connection: {}", e),
+            },
+            _ = shutdown.triggered() => return None,
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(ACCEPT_ERROR_DELAY) => {}
+            _ = shutdown.triggered() => return None,
+        }
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Serves HTTP/1 or HTTP/2 on a single connection. Once the shutdown is triggered, in-flight
+/// requests are completed and the connection is closed, or dropped when the grace period ends
+/// first.
+async fn serve_connection<I>(
+    io: I,
+    addresses: Addresses,
+    client_cert: Option<ClientCertificate>,
+    app: Router,
+    builder: Builder<TokioExecutor>,
+    shutdown: Shutdown,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let _connection = shutdown.connection();
+
+    let service = app.map_request(move |mut req: Request<Incoming>| {
+        req.extensions_mut().insert(ConnectInfo(addresses.source));
+        req.extensions_mut().insert(addresses);
+        if let Some(client_cert) = &client_cert {
+            req.extensions_mut().insert(client_cert.clone());
+        }
+        req
+    });
+
+    let connection =
+        builder.serve_connection_with_upgrades(TokioIo::new(io), TowerToHyperService::new(service));
+    tokio::pin!(connection);
+
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown.triggered() => {
+            connection.as_mut().graceful_shutdown();
+            tokio::select! {
+                result = connection.as_mut() => result,
+                _ = shutdown.closed() => {
+                    debug!("Closing the connection from {} after the grace period", addresses.source);
+                    return;
+                }
+            }
+        }
+    };
+
+    if let Err(e) = result {
+        debug!(
+            "Connection from {} closed with error: {}",
+            addresses.source, e
+        );
+    }
+}
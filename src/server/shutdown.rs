@@ -0,0 +1,108 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{signal, sync::watch};
+use tracing::info;
+
+/// Process-wide shutdown signal shared by every listener, together with a count of the client
+/// connections still being served.
+#[derive(Clone)]
+pub struct Shutdown {
+    triggered: Arc<watch::Sender<bool>>,
+    /// Set once the grace period is over, for connections still open to give up
+    closed: Arc<watch::Sender<bool>>,
+    connections: Arc<watch::Sender<usize>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            triggered: Arc::new(watch::Sender::new(false)),
+            closed: Arc::new(watch::Sender::new(false)),
+            connections: Arc::new(watch::Sender::new(0)),
+        }
+    }
+
+    /// Triggers the shutdown on Ctrl+C or `SIGTERM`.
+    pub fn listen_for_signals(&self) {
+        let shutdown = self.clone();
+
+        tokio::spawn(async move {
+            let ctrl_c = async {
+                signal::ctrl_c()
+                    .await
+                    .expect("failed to install Ctrl+C handler");
+            };
+
+            #[cfg(unix)]
+            let terminate = async {
+                signal::unix::signal(signal::unix::SignalKind::terminate())
+                    .expect("failed to install signal handler")
+                    .recv()
+                    .await;
+            };
+
+            #[cfg(not(unix))]
+            let terminate = std::future::pending::<()>();
+
+            tokio::select! {
+                _ = ctrl_c => {},
+                _ = terminate => {},
+            }
+
+            info!("Shutting down...");
+            shutdown.trigger();
+        });
+    }
+
+    pub fn trigger(&self) {
+        self.triggered.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.triggered.borrow()
+    }
+
+    /// Resolves once the shutdown has been triggered.
+    pub async fn triggered(&self) {
+        let mut triggered = self.triggered.subscribe();
+        let _ = triggered.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Counts a client connection as open until the guard is dropped.
+    pub fn connection(&self) -> ConnectionGuard {
+        self.connections.send_modify(|count| *count += 1);
+
+        ConnectionGuard {
+            connections: self.connections.clone(),
+        }
+    }
+
+    /// Resolves once the grace period is over.
+    pub async fn closed(&self) {
+        let mut closed = self.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    /// Waits up to `grace` for every connection to close, then closes the others, returning how
+    /// many were left.
+    pub async fn wait_for_connections(&self, grace: Duration) -> usize {
+        let mut connections = self.connections.subscribe();
+        let _ = tokio::time::timeout(grace, connections.wait_for(|count| *count == 0)).await;
+
+        let remaining = *connections.borrow();
+        if remaining > 0 {
+            self.closed.send_replace(true);
+        }
+        remaining
+    }
+}
+
+pub struct ConnectionGuard {
+    connections: Arc<watch::Sender<usize>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.send_modify(|count| *count -= 1);
+    }
+}
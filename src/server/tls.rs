@@ -0,0 +1,130 @@
+use std::{error::Error, io, sync::Arc};
+
+use rustls::{
+    crypto::ring,
+    server::{danger::ClientCertVerifier, Acceptor, WebPkiClientVerifier},
+    RootCertStore, ServerConfig,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{server::TlsStream, LazyConfigAcceptor};
+
+use super::client_cert::ClientCertificate;
+use crate::{
+    env::state::{AppState, ClientAuthMode, TlsConfig},
+    utils::pem::{load_certs, load_private_key},
+};
+
+/// Server configurations for the TLS listener, one per client certificate policy.
+///
+/// The policy is picked per connection from the route matching the SNI in the ClientHello.
+#[derive(Debug)]
+pub struct ServerTls {
+    no_client_auth: Arc<ServerConfig>,
+    optional_client_auth: Option<Arc<ServerConfig>>,
+    required_client_auth: Option<Arc<ServerConfig>>,
+}
+
+impl ServerTls {
+    pub fn from_config(config: &TlsConfig, http2: bool) -> Result<Self, Box<dyn Error>> {
+        let provider = Arc::new(ring::default_provider());
+        let certs = load_certs(&config.cert)?;
+        let key = load_private_key(&config.key)?;
+
+        let build = |verifier: Option<Arc<dyn ClientCertVerifier>>| {
+            let builder = ServerConfig::builder_with_provider(provider.clone())
+                .with_safe_default_protocol_versions()?;
+            let builder = match verifier {
+                Some(verifier) => builder.with_client_cert_verifier(verifier),
+                None => builder.with_no_client_auth(),
+            };
+
+            let mut server_config = builder.with_single_cert(certs.clone(), key.clone_key())?;
+            server_config.alpn_protocols = if http2 {
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            } else {
+                vec![b"http/1.1".to_vec()]
+            };
+
+            Ok::<_, rustls::Error>(Arc::new(server_config))
+        };
+
+        let (optional_client_auth, required_client_auth) = match &config.client_ca {
+            Some(client_ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(client_ca)? {
+                    roots.add(cert)?;
+                }
+                let roots = Arc::new(roots);
+
+                let required =
+                    WebPkiClientVerifier::builder_with_provider(roots.clone(), provider.clone())
+                        .build()?;
+                let optional = WebPkiClientVerifier::builder_with_provider(roots, provider.clone())
+                    .allow_unauthenticated()
+                    .build()?;
+
+                (Some(build(Some(optional))?), Some(build(Some(required))?))
+            }
+            None => (None, None),
+        };
+
+        Ok(Self {
+            no_client_auth: build(None)?,
+            optional_client_auth,
+            required_client_auth,
+        })
+    }
+
+    /// Configuration for QUIC, where the certificate request cannot depend on the SNI. Client
+    /// certificates are never requested, so browsers don't prompt for one on every domain, and
+    /// `Alt-Svc` is left out for domains whose routes use them.
+    pub fn quic_config(&self) -> ServerConfig {
+        let mut config = ServerConfig::clone(&self.no_client_auth);
+        config.alpn_protocols = vec![b"h3".to_vec()];
+        config.max_early_data_size = 0;
+        config
+    }
+
+    fn server_config(&self, mode: Option<ClientAuthMode>) -> Arc<ServerConfig> {
+        let config = match mode {
+            Some(ClientAuthMode::Required) => self.required_client_auth.as_ref(),
+            Some(ClientAuthMode::Optional) => self.optional_client_auth.as_ref(),
+            None => None,
+        };
+
+        config.unwrap_or(&self.no_client_auth).clone()
+    }
+}
+
+/// Completes the TLS handshake, requesting a client certificate when the route for the SNI
+/// domain asks for one.
+pub async fn accept<S: AsyncRead + AsyncWrite + Unpin>(
+    state: &AppState,
+    stream: S,
+) -> io::Result<(TlsStream<S>, Option<ClientCertificate>)> {
+    let server_tls = state
+        .server_tls
+        .read()
+        .await
+        .clone()
+        .ok_or_else(|| io::Error::other("TLS listener is not configured"))?;
+
+    let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+
+    let mode = {
+        let client_hello = start.client_hello();
+        let domain = client_hello.server_name().unwrap_or("*");
+
+        state.route_table.read().await.client_auth_mode(domain)
+    };
+
+    let stream = start.into_stream(server_tls.server_config(mode)).await?;
+    let client_cert = stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .and_then(ClientCertificate::from_der);
+
+    Ok((stream, client_cert))
+}
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::{Child, Command},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncReadExt,
+    net::unix::pipe,
+    signal::unix::{signal, SignalKind},
+};
+use tracing::{error, info, warn};
+
+use super::{listeners::LISTEN_FDS_ENV, shutdown::Shutdown};
+use crate::env::state::{AppState, Config};
+
+const STATE_FILE_ENV: &str = "TRAFFIC_SWITCHER_STATE_FILE";
+const READY_FD_ENV: &str = "TRAFFIC_SWITCHER_READY_FD";
+
+/// Runtime state handed to the new process, overriding what it reads from `config.yaml`.
+#[derive(Debug, Serialize, Deserialize)]
+struct RuntimeState {
+    services: HashMap<String, ServicePorts>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ServicePorts {
+    port: u16,
+    previous_port: Option<u16>,
+}
+
+/// On `SIGUSR2`, starts a new process of the current binary on the same listening sockets and
+/// shuts this one down gracefully once the new one is ready.
+pub fn listen_for_upgrade(state: AppState, fds: Vec<(String, RawFd)>, api: Shutdown) {
+    tokio::spawn(async move {
+        let Ok(mut signal) = signal(SignalKind::user_defined2()) else {
+            error!("Failed to install SIGUSR2 handler for binary upgrades");
+            return;
+        };
+
+        while signal.recv().await.is_some() {
+            if state.shutdown.is_triggered() {
+                continue;
+            }
+
+            info!("Upgrading: starting a new process on the inherited listeners");
+            match upgrade(&state, &fds).await {
+                Ok(pid) => {
+                    info!("New process {} is ready, handing over", pid);
+                    api.trigger();
+                    state.shutdown.trigger();
+                    break;
+                }
+                Err(e) => error!("Upgrade failed, keeping the current process: {}", e),
+            }
+        }
+    });
+}
+
+async fn upgrade(state: &AppState, fds: &[(String, RawFd)]) -> Result<u32, String> {
+    let config = state.config.read().await.clone();
+    let state_file = config.upgrade.state_file.clone();
+    let ready_timeout = Duration::from_secs(config.upgrade.ready_timeout_seconds);
+
+    write_state(&config, &state_file)
+        .await
+        .map_err(|e| format!("Failed to write {}: {}", state_file, e))?;
+
+    let (ready_read, ready_write) = ready_pipe().map_err(|e| e.to_string())?;
+    let mut child = spawn(fds, &state_file, &ready_write).map_err(|e| e.to_string())?;
+    drop(ready_write);
+
+    match wait_ready(ready_read, ready_timeout).await {
+        Ok(()) => Ok(child.id()),
+        Err(e) => {
+            stop(&mut child);
+            Err(e)
+        }
+    }
+}
+
+pub async fn write_state(config: &Config, path: &str) -> io::Result<()> {
+    let state = RuntimeState {
+        services: config
+            .services
+            .iter()
+            .map(|service| {
+                let ports = ServicePorts {
+                    port: service.port,
+                    previous_port: service.previous_port,
+                };
+                (service.name.clone(), ports)
+            })
+            .collect(),
+    };
+
+    tokio::fs::write(path, serde_json::to_vec_pretty(&state)?).await
+}
+
+pub fn ready_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+
+    // SAFETY: `fds` has room for the two descriptors `pipe2` writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: both descriptors were just created and are owned by nobody else
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Environment of the new process, read back by `Inherited::take` and `Handover::take`.
+pub fn handover_env(
+    fds: &[(String, RawFd)],
+    state_file: &str,
+    ready_fd: RawFd,
+) -> [(&'static str, String); 3] {
+    let listen_fds = fds
+        .iter()
+        .map(|(name, fd)| format!("{}:{}", name, fd))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    [
+        (LISTEN_FDS_ENV, listen_fds),
+        (STATE_FILE_ENV, state_file.to_string()),
+        (READY_FD_ENV, ready_fd.to_string()),
+    ]
+}
+
+fn spawn(fds: &[(String, RawFd)], state_file: &str, ready: &OwnedFd) -> io::Result<Child> {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| "traffic_switcher".into());
+
+    let ready_fd = ready.as_raw_fd();
+    let inherited: Vec<RawFd> = fds.iter().map(|(_, fd)| *fd).chain([ready_fd]).collect();
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .envs(handover_env(fds, state_file, ready_fd));
+
+    // SAFETY: only async-signal-safe `fcntl` calls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            for fd in &inherited {
+                if libc::fcntl(*fd, libc::F_SETFD, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+
+    command.spawn()
+}
+
+pub async fn wait_ready(ready: OwnedFd, timeout: Duration) -> Result<(), String> {
+    let mut receiver = pipe::Receiver::from_owned_fd(ready).map_err(|e| e.to_string())?;
+    let mut byte = [0u8; 1];
+
+    match tokio::time::timeout(timeout, receiver.read(&mut byte)).await {
+        Ok(Ok(1)) => Ok(()),
+        Ok(Ok(_)) => Err("the new process exited before becoming ready".to_string()),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err(format!(
+            "the new process was not ready within {:?}",
+            timeout
+        )),
+    }
+}
+
+fn stop(child: &mut Child) {
+    if let Err(e) = child.kill() {
+        warn!("Failed to stop new process {}: {}", child.id(), e);
+    }
+    let _ = child.wait();
+}
+
+/// What the process that started this one passed in the environment, taken before any other
+/// thread starts since changing the environment is not thread-safe.
+pub struct Handover {
+    state_file: Option<String>,
+    ready_fd: Option<RawFd>,
+}
+
+impl Handover {
+    pub fn take() -> Self {
+        let state_file = std::env::var(STATE_FILE_ENV).ok();
+        let ready_fd = std::env::var(READY_FD_ENV)
+            .ok()
+            .and_then(|fd| fd.parse::<RawFd>().ok());
+        std::env::remove_var(STATE_FILE_ENV);
+        std::env::remove_var(READY_FD_ENV);
+
+        Self {
+            state_file,
+            ready_fd,
+        }
+    }
+
+    /// Applies the ports carried over from the previous process, if this process was started
+    /// by an upgrade.
+    pub async fn restore_state(&self, config: &mut Config) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+
+        let state = match tokio::fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice::<RuntimeState>(&bytes).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let state = match state {
+            Ok(state) => state,
+            Err(e) => {
+                error!("Failed to read runtime state from {}: {}", path, e);
+                return;
+            }
+        };
+
+        for service in &mut config.services {
+            if let Some(ports) = state.services.get(&service.name) {
+                service.port = ports.port;
+                service.previous_port = ports.previous_port;
+            }
+        }
+
+        info!("Restored runtime state from {}", path);
+        let _ = tokio::fs::remove_file(path).await;
+    }
+
+    /// Tells the process that started this one that the listeners are being served.
+    pub fn notify_ready(self) {
+        let Some(fd) = self.ready_fd else {
+            return;
+        };
+
+        // SAFETY: the descriptor is the write end of the readiness pipe passed by the parent
+        let ready = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut file = std::fs::File::from(ready);
+        if let Err(e) = io::Write::write_all(&mut file, &[1]) {
+            warn!("Failed to notify the previous process: {}", e);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use axum::{
+        body::{Body, Bytes},
+        extract::{Request, State},
+        http::StatusCode,
+        middleware::from_fn_with_state,
+        routing::post,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use opentelemetry::{global, trace::TracerProvider as _};
+    use opentelemetry_proto::tonic::{
+        collector::trace::v1::ExportTraceServiceRequest, common::v1::any_value::Value,
+        trace::v1::Span,
+    };
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use prost::Message;
+    use tokio::{
+        net::TcpListener,
+        sync::mpsc::{self, UnboundedSender},
+    };
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::env::state::{AppState, Config, OtlpConfig, OtlpProtocol, RequestIdConfig};
+    use crate::routes::proxy::proxy_handler;
+    use crate::telemetry::build_provider;
+    use crate::utils::request_id::{self, RequestIds};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    /// Stand-in for an OTLP/HTTP collector, passing on every exported span.
+    async fn collector(spans: UnboundedSender<Span>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new()
+            .route(
+                "/v1/traces",
+                post(
+                    |State(spans): State<UnboundedSender<Span>>, body: Bytes| async move {
+                        let export = ExportTraceServiceRequest::decode(body).unwrap();
+                        export
+                            .resource_spans
+                            .into_iter()
+                            .flat_map(|resource| resource.scope_spans)
+                            .flat_map(|scope| scope.spans)
+                            .for_each(|span| spans.send(span).unwrap());
+                        StatusCode::OK
+                    },
+                ),
+            )
+            .with_state(spans);
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    /// Upstream answering with the `traceparent` it received.
+    async fn upstream() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let app = Router::new().fallback(|req: Request| async move {
+            req.headers()
+                .get("traceparent")
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default()
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        port
+    }
+
+    fn attribute<'a>(span: &'a Span, key: &str) -> Option<&'a str> {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key == key)
+            .and_then(|attribute| attribute.value.as_ref()?.value.as_ref())
+            .and_then(|value| match value {
+                Value::StringValue(value) => Some(value.as_str()),
+                _ => None,
+            })
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn should_export_request_spans_and_continue_incoming_traces() {
+        let (sender, mut spans) = mpsc::unbounded_channel();
+        let provider = build_provider(&OtlpConfig {
+            endpoint: format!("http://127.0.0.1:{}", collector(sender).await),
+            protocol: OtlpProtocol::Http,
+            sample_ratio: 1.0,
+            service_name: "traffic-switcher".to_string(),
+        })
+        .unwrap();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        // Spans of the request are all created on this thread, where the subscriber is installed
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("traffic_switcher")));
+        let _subscriber = tracing::subscriber::set_default(subscriber);
+
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes:
+    - domain: example.com
+      type: service
+      service: app
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+",
+            upstream().await
+        ))
+        .unwrap();
+        let request_ids = RequestIds::from_config(&RequestIdConfig::default()).unwrap();
+        let app = Router::new()
+            .fallback(proxy_handler)
+            .with_state(AppState::new(config))
+            .layer(from_fn_with_state(
+                Arc::new(request_ids),
+                request_id::middleware,
+            ));
+
+        let req = Request::get("/")
+            .header("Host", "example.com")
+            .header("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_ID))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(req).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let request_id = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        // The upstream is called from a child span of the caller's trace
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let traceparent = String::from_utf8(body.to_vec()).unwrap();
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "{}", traceparent);
+        assert_eq!(parts[1], TRACE_ID);
+        assert_ne!(parts[2], PARENT_ID);
+        assert_eq!(parts[3], "01");
+
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let request_span = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let span = spans.recv().await.unwrap();
+                if attribute(&span, "request_id").is_some() {
+                    return span;
+                }
+            }
+        })
+        .await
+        .expect("the request span should be exported");
+
+        assert_eq!(attribute(&request_span, "request_id"), Some(&*request_id));
+        assert_eq!(hex(&request_span.trace_id), TRACE_ID);
+        assert_eq!(hex(&request_span.parent_span_id), PARENT_ID);
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
@@ -0,0 +1,154 @@
+mod __tests__;
+
+use axum::{extract::Request, http::HeaderMap};
+use opentelemetry::{
+    global,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tracing::{field::Empty, info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::env::state::{OtlpConfig, OtlpProtocol};
+
+/// Installs the global tracing subscriber, exporting spans over OTLP when `otlp` is configured.
+///
+/// The returned provider has to be shut down on exit so buffered spans are flushed.
+pub fn init(otlp: Option<&OtlpConfig>) -> Option<TracerProvider> {
+    let (provider, setup_error) = match otlp.map(build_provider) {
+        Some(Ok(provider)) => (Some(provider), None),
+        Some(Err(e)) => (None, Some(e)),
+        None => (None, None),
+    };
+
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("traffic_switcher"))
+    });
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(otel_layer)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .compact(),
+        )
+        .init();
+
+    // Reported only now, once the subscriber is there to record it
+    if let Some(e) = setup_error {
+        tracing::error!("Failed to set up OTLP exporter: {}", e);
+    }
+
+    if let Some(config) = otlp.filter(|_| provider.is_some()) {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing::info!(
+            "Exporting traces to {} over {:?} (sample ratio {})",
+            config.endpoint,
+            config.protocol,
+            config.sample_ratio
+        );
+    }
+
+    provider
+}
+
+pub fn build_provider(config: &OtlpConfig) -> Result<TracerProvider, TraceError> {
+    let exporter = match config.protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint.clone())
+            .build()?,
+        OtlpProtocol::Http => {
+            let endpoint = config.endpoint.trim_end_matches('/');
+            let endpoint = if endpoint.ends_with("/v1/traces") {
+                endpoint.to_string()
+            } else {
+                format!("{}/v1/traces", endpoint)
+            };
+
+            SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()?
+        }
+    };
+
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            config.service_name.clone(),
+        )]))
+        .build())
+}
+
+pub fn shutdown(provider: TracerProvider) {
+    if let Err(e) = provider.shutdown() {
+        tracing::error!("Failed to flush traces: {}", e);
+    }
+}
+
+/// Server span covering a proxied request, continuing the caller's trace when one is present.
+///
+/// `route`, `service` and `upstream` are recorded by the proxy handler once the route is known.
+pub fn request_span(req: &Request, request_id: &str) -> Span {
+    let span = info_span!(
+        "request",
+        request_id = %request_id,
+        otel.name = %req.method(),
+        otel.kind = "server",
+        http.request.method = %req.method(),
+        url.path = %req.uri().path(),
+        server.address = Empty,
+        route = Empty,
+        service = Empty,
+        upstream = Empty,
+        http.response.status_code = Empty,
+    );
+
+    if let Some(host) = req
+        .headers()
+        .get(axum::http::header::HOST)
+        .and_then(|value| value.to_str().ok())
+    {
+        span.record("server.address", host);
+    }
+
+    set_parent_from_headers(&span, req.headers());
+    span
+}
+
+/// Continues the trace described by the `traceparent`/`tracestate` headers, if any.
+pub fn set_parent_from_headers(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(context);
+}
+
+/// Writes the W3C trace context of `span` into outgoing headers.
+pub fn inject_headers(span: &Span, headers: &mut HeaderMap) {
+    let context = span.context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+
+    if headers
+        .get("tracestate")
+        .is_some_and(|value| value.is_empty())
+    {
+        headers.remove("tracestate");
+    }
+}
@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use crate::env::state::CircuitBreakerConfig;
+    use crate::metrics::Metrics;
+    use crate::upstream::breaker::{BreakerState, CircuitBreakers};
+
+    fn config(yaml: &str) -> CircuitBreakerConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn state(breakers: &CircuitBreakers) -> BreakerState {
+        breakers.status()[0].state
+    }
+
+    #[test]
+    fn should_open_after_consecutive_failures_and_close_after_probe() {
+        let breakers = CircuitBreakers::new(Arc::new(Metrics::new()));
+        let config = config("consecutive_failures: 2\ncooldown_seconds: 0");
+
+        breakers.record("app", "10.0.0.1:80", &config, false);
+        assert!(breakers.allow("app", "10.0.0.1:80", &config));
+        breakers.record("app", "10.0.0.1:80", &config, false);
+        assert_eq!(state(&breakers), BreakerState::Open);
+
+        assert!(breakers.allow("app", "10.0.0.1:80", &config));
+        assert_eq!(state(&breakers), BreakerState::HalfOpen);
+
+        breakers.record("app", "10.0.0.1:80", &config, true);
+        assert_eq!(state(&breakers), BreakerState::Closed);
+    }
+
+    #[test]
+    fn should_open_on_error_rate() {
+        let breakers = CircuitBreakers::new(Arc::new(Metrics::new()));
+        let config = config("consecutive_failures: 0\nerror_rate_percent: 50\nmin_requests: 4");
+
+        for success in [true, false, true] {
+            breakers.record("app", "10.0.0.1:80", &config, success);
+        }
+        assert_eq!(state(&breakers), BreakerState::Closed);
+
+        breakers.record("app", "10.0.0.1:80", &config, false);
+        assert_eq!(state(&breakers), BreakerState::Open);
+        assert!(!breakers.allow("app", "10.0.0.1:80", &config));
+    }
+
+    #[test]
+    fn should_forget_endpoints_that_are_gone() {
+        let breakers = CircuitBreakers::new(Arc::new(Metrics::new()));
+        let config = config("consecutive_failures: 1");
+
+        breakers.record("app", "10.0.0.1:80", &config, false);
+        breakers.record("app", "10.0.0.1:81", &config, false);
+
+        let current = HashSet::from([("app".to_string(), "10.0.0.1:81".to_string())]);
+        breakers.retain(&current);
+
+        let status = breakers.status();
+        assert_eq!(status.len(), 1);
+        assert_eq!(status[0].address, "10.0.0.1:81");
+    }
+}
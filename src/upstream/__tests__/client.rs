@@ -0,0 +1,56 @@
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, Version};
+
+    use crate::upstream::client::{is_upgrade, remove_connection_headers};
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn should_only_pass_upgrades_on_between_http1_peers() {
+        let upgrade = headers(&[
+            ("connection", "keep-alive, Upgrade"),
+            ("upgrade", "websocket"),
+        ]);
+        assert!(is_upgrade(Version::HTTP_11, &upgrade));
+        assert!(!is_upgrade(Version::HTTP_2, &upgrade));
+        assert!(!is_upgrade(
+            Version::HTTP_11,
+            &headers(&[("upgrade", "websocket")])
+        ));
+
+        let mut kept = upgrade.clone();
+        remove_connection_headers(&mut kept, true, true);
+        assert_eq!(kept["upgrade"], "websocket");
+        assert_eq!(kept["connection"], "upgrade");
+
+        let mut translated = upgrade;
+        remove_connection_headers(&mut translated, true, false);
+        assert!(translated.is_empty());
+    }
+
+    #[test]
+    fn should_remove_connection_headers() {
+        let mut request = headers(&[
+            ("connection", "close, x-hop"),
+            ("x-hop", "1"),
+            ("keep-alive", "timeout=5"),
+            ("te", "trailers"),
+            ("x-end-to-end", "1"),
+        ]);
+        remove_connection_headers(&mut request, true, false);
+        assert_eq!(
+            request,
+            headers(&[("te", "trailers"), ("x-end-to-end", "1")])
+        );
+
+        let mut response = headers(&[("te", "trailers"), ("transfer-encoding", "chunked")]);
+        remove_connection_headers(&mut response, false, false);
+        assert!(response.is_empty());
+    }
+}
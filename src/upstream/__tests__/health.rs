@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use tokio::net::TcpListener;
+
+    use crate::env::state::{AppState, Config};
+    use crate::upstream::health::{grpc_request, grpc_serving_status, put_varint, take_varint};
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        frame.extend_from_slice(message);
+        frame
+    }
+
+    #[test]
+    fn should_encode_health_check_requests() {
+        assert_eq!(grpc_request(""), frame(&[]));
+        assert_eq!(
+            grpc_request("orders.v1.Orders"),
+            frame(b"\x0a\x10orders.v1.Orders")
+        );
+    }
+
+    #[test]
+    fn should_read_serving_status() {
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0x01])), Some(1));
+        assert_eq!(grpc_serving_status(&frame(&[0x08, 0x02])), Some(2));
+        // Unknown fields before the status are skipped
+        assert_eq!(
+            grpc_serving_status(&frame(&[0x12, 0x02, b'h', b'i', 0x18, 0x05, 0x08, 0x01])),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn should_default_to_unknown_status() {
+        assert_eq!(grpc_serving_status(&frame(&[])), Some(0));
+        assert_eq!(grpc_serving_status(&frame(&[0x18, 0x05])), Some(0));
+        assert_eq!(grpc_serving_status(&[0, 0]), None);
+        assert_eq!(grpc_serving_status(&frame(&[0x08])), None);
+        assert_eq!(grpc_serving_status(&frame(&[0x0d, 0, 0, 0, 0])), None);
+    }
+
+    #[test]
+    fn should_round_trip_varints() {
+        for value in [0, 1, 127, 128, 300, u64::from(u32::MAX), u64::MAX] {
+            let mut buf = Vec::new();
+            put_varint(&mut buf, value);
+
+            let mut slice = buf.as_slice();
+            assert_eq!(take_varint(&mut slice), Some(value));
+            assert!(slice.is_empty());
+        }
+
+        let mut buf = Vec::new();
+        put_varint(&mut buf, 300);
+        assert_eq!(buf, [0xac, 0x02]);
+        assert_eq!(take_varint(&mut &[0x80, 0x80][..]), None);
+    }
+
+    async fn serve(listener: TcpListener) {
+        tokio::spawn(async move { axum::serve(listener, Router::new()).await });
+    }
+
+    #[tokio::test]
+    async fn should_check_every_endpoint_before_switching() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = first.local_addr().unwrap().port();
+        serve(first).await;
+
+        let config: Config = serde_yaml::from_str(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: app
+      host: 127.0.0.1
+      endpoints: [127.0.0.2]
+      port: 1
+      health_check:
+          retry_count: 1
+          retry_delay_seconds: 0
+",
+        )
+        .unwrap();
+        let state = AppState::new(config);
+
+        assert!(state.update_service_port("app", port, false).await.is_err());
+
+        serve(TcpListener::bind(("127.0.0.2", port)).await.unwrap()).await;
+        assert_eq!(state.update_service_port("app", port, false).await, Ok(1));
+    }
+}
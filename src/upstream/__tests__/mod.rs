@@ -0,0 +1,7 @@
+mod breaker;
+mod client;
+mod health;
+mod retry;
+mod timeout;
+mod tls;
+mod upstream;
@@ -0,0 +1,71 @@
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use crate::env::state::{AppState, Config, RetryConfig};
+    use crate::upstream::retry::{backoff, RetryBudget};
+
+    fn config() -> RetryConfig {
+        serde_yaml::from_str("budget_ratio: 0.5\nbudget_burst: 2").unwrap()
+    }
+
+    #[test]
+    fn should_limit_retries_to_the_budget() {
+        let budget = RetryBudget::new(&config());
+
+        assert!(budget.try_withdraw());
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+
+        budget.deposit();
+        assert!(!budget.try_withdraw());
+        budget.deposit();
+        assert!(budget.try_withdraw());
+    }
+
+    #[test]
+    fn should_cap_backoff() {
+        let config = config();
+
+        for retry in 1..20 {
+            assert!(backoff(&config, retry).as_millis() <= u128::from(config.backoff_max_ms));
+        }
+    }
+
+    fn services(burst: u32) -> Config {
+        serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: app
+      host: 127.0.0.1
+      port: 3000
+      retry:
+          budget_burst: {}
+",
+            burst
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn should_keep_budgets_of_unchanged_services() {
+        let budgets = AppState::build_retry_budgets(&services(1), &HashMap::new());
+        assert!(budgets["app"].try_withdraw());
+
+        let reloaded = AppState::build_retry_budgets(&services(1), &budgets);
+        assert!(Arc::ptr_eq(&reloaded["app"], &budgets["app"]));
+        assert!(!reloaded["app"].try_withdraw());
+
+        let changed = AppState::build_retry_budgets(&services(2), &reloaded);
+        assert!(changed["app"].try_withdraw());
+    }
+
+    #[test]
+    fn should_reject_an_empty_burst() {
+        assert!(AppState::check_retry(&services(0)).is_err());
+        assert!(AppState::check_retry(&services(1)).is_ok());
+    }
+}
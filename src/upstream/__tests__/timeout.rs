@@ -0,0 +1,123 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration,
+    };
+
+    use axum::body::{Bytes, HttpBody};
+    use http_body_util::BodyExt;
+    use hyper::body::Frame;
+
+    use crate::env::state::TimeoutConfig;
+    use crate::upstream::timeout::{TimeoutBody, TimeoutError, TimeoutKind, Timeouts};
+
+    /// Body sending its chunks right away and then stalling forever.
+    struct Stalled(VecDeque<Bytes>);
+
+    impl HttpBody for Stalled {
+        type Data = Bytes;
+        type Error = std::convert::Infallible;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+            match self.0.pop_front() {
+                Some(chunk) => Poll::Ready(Some(Ok(Frame::data(chunk)))),
+                None => Poll::Pending,
+            }
+        }
+    }
+
+    fn timeouts(idle: Option<u64>, total: Option<u64>) -> Timeouts {
+        Timeouts {
+            connect: None,
+            first_byte: None,
+            idle: idle.map(Duration::from_secs),
+            total: total.map(Duration::from_secs),
+        }
+    }
+
+    async fn next_error<B>(body: &mut TimeoutBody<B>) -> TimeoutError
+    where
+        B: HttpBody<Data = Bytes> + Unpin,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        loop {
+            match body.frame().await {
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return *e.downcast::<TimeoutError>().unwrap(),
+                None => panic!("body ended without a timeout"),
+            }
+        }
+    }
+
+    #[test]
+    fn should_override_global_timeouts_per_field() {
+        let global = TimeoutConfig {
+            connect_ms: Some(1000),
+            idle_ms: Some(2000),
+            ..Default::default()
+        };
+        let service = TimeoutConfig {
+            idle_ms: Some(0),
+            total_ms: Some(30000),
+            ..Default::default()
+        };
+
+        let timeouts = Timeouts::resolve(&global, Some(&service));
+
+        assert_eq!(timeouts.connect, Some(Duration::from_secs(1)));
+        assert_eq!(timeouts.first_byte, None);
+        assert_eq!(timeouts.idle, None);
+        assert_eq!(timeouts.total, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn should_only_bound_connect_by_default() {
+        let timeouts = Timeouts::resolve(&TimeoutConfig::default(), None);
+
+        assert_eq!(timeouts.connect, Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.first_byte, None);
+        assert_eq!(timeouts.idle, None);
+        assert_eq!(timeouts.total, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_fail_an_idle_body() {
+        let started = tokio::time::Instant::now();
+        let chunks = VecDeque::from([Bytes::from("first")]);
+        let mut body = TimeoutBody::new(
+            Stalled(chunks),
+            "app".to_string(),
+            &timeouts(Some(10), None),
+            None,
+        );
+
+        let error = next_error(&mut body).await;
+
+        assert_eq!(error.kind, TimeoutKind::Idle);
+        assert_eq!(started.elapsed(), Duration::from_secs(10));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_fail_a_body_past_the_deadline() {
+        let started = tokio::time::Instant::now();
+        let mut body = TimeoutBody::new(
+            Stalled(VecDeque::new()),
+            "app".to_string(),
+            &timeouts(Some(60), Some(30)),
+            Some(started + Duration::from_secs(5)),
+        );
+
+        let error = next_error(&mut body).await;
+
+        // The deadline was taken when the request started, not when the body did
+        assert_eq!(error.kind, TimeoutKind::Total);
+        assert_eq!(error.after, Duration::from_secs(30));
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+    }
+}
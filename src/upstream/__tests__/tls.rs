@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, path::PathBuf, sync::Arc};
+
+    use axum::{body::Body, extract::Request, http::StatusCode};
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use hyper::{server::conn::http1, service::service_fn, Response};
+    use hyper_util::rt::TokioIo;
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+    use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::env::state::{AppState, Config};
+    use crate::routes::proxy::proxy_request;
+    use crate::utils::pem::{load_certs, load_private_key};
+
+    struct Pki {
+        ca: PathBuf,
+        server: (PathBuf, PathBuf),
+        client: (PathBuf, PathBuf),
+    }
+
+    /// A CA signing a server certificate for `backend.internal` and a client certificate.
+    fn pki() -> Pki {
+        let dir = std::env::temp_dir().join(format!(
+            "traffic-switcher-upstream-tls-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params
+            .distinguished_name
+            .push(DnType::CommonName, "Test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_key = KeyPair::generate().unwrap();
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf = |name: &str, usage: ExtendedKeyUsagePurpose| {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.extended_key_usages = vec![usage];
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &ca_cert, &ca_key).unwrap();
+
+            let (cert_path, key_path) = (
+                dir.join(format!("{}.pem", name)),
+                dir.join(format!("{}-key.pem", name)),
+            );
+            std::fs::write(&cert_path, cert.pem()).unwrap();
+            std::fs::write(&key_path, key.serialize_pem()).unwrap();
+            (cert_path, key_path)
+        };
+
+        let ca = dir.join("ca.pem");
+        std::fs::write(&ca, ca_cert.pem()).unwrap();
+
+        Pki {
+            server: leaf("backend.internal", ExtendedKeyUsagePurpose::ServerAuth),
+            client: leaf("switcher", ExtendedKeyUsagePurpose::ClientAuth),
+            ca,
+        }
+    }
+
+    /// HTTPS upstream requiring a client certificate from the CA, answering with the SNI it
+    /// received.
+    async fn tls_upstream(pki: &Pki) -> u16 {
+        let path = |p: &PathBuf| p.to_str().unwrap().to_string();
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(&path(&pki.ca)).unwrap() {
+            roots.add(cert).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let verifier = WebPkiClientVerifier::builder_with_provider(roots.into(), provider.clone())
+            .build()
+            .unwrap();
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                load_certs(&path(&pki.server.0)).unwrap(),
+                load_private_key(&path(&pki.server.1)).unwrap(),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let sni = stream.get_ref().1.server_name().unwrap_or("").to_string();
+                    let service = service_fn(move |_req| {
+                        let sni = sni.clone();
+                        async move { Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(sni)))) }
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        port
+    }
+
+    async fn send(port: u16, tls: &str) -> Result<(StatusCode, Bytes), StatusCode> {
+        let config: Config = serde_yaml::from_str(&format!(
+            "
+api_port: 0
+proxy_port: 0
+routes: []
+services:
+    - name: app
+      host: 127.0.0.1
+      port: {}
+      scheme: https
+      tls:
+{}
+",
+            port, tls
+        ))
+        .unwrap();
+        let state = AppState::new(config.clone());
+        let upstream = state.upstreams(&config.services[0]).await.remove(0);
+        let req = Request::get("/").body(Body::empty()).unwrap();
+
+        let response = proxy_request(&state, req, &upstream, None)
+            .await
+            .map_err(|e| e.status())?;
+        let status = response.status();
+        Ok((
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn should_verify_upstreams_with_the_configured_ca_and_server_name() {
+        let pki = pki();
+        let port = tls_upstream(&pki).await;
+        let (cert, key) = (pki.client.0.display(), pki.client.1.display());
+
+        let response = send(
+            port,
+            &format!(
+                "          ca_file: {}\n          server_name: backend.internal\n          client_cert: {}\n          client_key: {}",
+                pki.ca.display(),
+                cert,
+                key
+            ),
+        )
+        .await;
+        assert_eq!(
+            response,
+            Ok((StatusCode::OK, Bytes::from_static(b"backend.internal")))
+        );
+
+        // The certificate doesn't name the address connected to
+        let response = send(
+            port,
+            &format!(
+                "          ca_file: {}\n          client_cert: {}\n          client_key: {}",
+                pki.ca.display(),
+                cert,
+                key
+            ),
+        )
+        .await;
+        assert_eq!(response, Err(StatusCode::BAD_GATEWAY));
+
+        // The upstream requires a client certificate
+        let response = send(
+            port,
+            &format!(
+                "          ca_file: {}\n          server_name: backend.internal",
+                pki.ca.display()
+            ),
+        )
+        .await;
+        assert!(response.is_err());
+    }
+}
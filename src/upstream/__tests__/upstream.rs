@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::pki_types::ServerName;
+
+    use crate::env::state::{Service, TimeoutConfig};
+    use crate::upstream::{timeout::Timeouts, tls::UpstreamTls, Upstream};
+
+    /// Upstream for the first host of a service given as YAML.
+    fn upstream(yaml: &str) -> Upstream {
+        let service: Service = serde_yaml::from_str(yaml).unwrap();
+        let tls = service
+            .tls
+            .as_ref()
+            .map(|tls| Arc::new(UpstreamTls::from_config(tls, service.protocol).unwrap()));
+        let timeouts = Timeouts::resolve(&TimeoutConfig::default(), None);
+        Upstream::new(&service, &service.host, service.port, tls, timeouts)
+    }
+
+    #[test]
+    fn should_forward_the_client_host_without_upstream_host() {
+        let upstream = upstream("{name: app, host: backend.internal, port: 8080}");
+
+        assert_eq!(
+            upstream.request_host(Some("shop.example.com")),
+            Some("shop.example.com".to_string())
+        );
+        assert_eq!(upstream.request_host(None), None);
+        // The TLS server name stays the host connected to
+        assert_eq!(upstream.server_name(Some("shop.example.com")), None);
+    }
+
+    #[test]
+    fn should_follow_upstream_host() {
+        let preserve = upstream(
+            "{name: app, host: backend.internal, port: 8443, scheme: https, tls: {}, upstream_host: preserve}",
+        );
+        assert_eq!(
+            preserve.request_host(Some("shop.example.com:8443")),
+            Some("shop.example.com:8443".to_string())
+        );
+        assert_eq!(
+            preserve.server_name(Some("shop.example.com:8443")),
+            Some("shop.example.com".to_string())
+        );
+
+        let literal = upstream(
+            "{name: app, host: backend.internal, port: 8443, upstream_host: api.example.com}",
+        );
+        assert_eq!(
+            literal.request_host(Some("shop.example.com")),
+            Some("api.example.com".to_string())
+        );
+        assert_eq!(
+            literal.server_name(Some("api.example.com")),
+            Some("api.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn should_leave_default_ports_out_of_the_service_host() {
+        let host = |yaml: &str| upstream(yaml).request_host(Some("shop.example.com"));
+
+        assert_eq!(
+            host("{name: app, host: backend.internal, port: 80, upstream_host: service}"),
+            Some("backend.internal".to_string())
+        );
+        assert_eq!(
+            host("{name: app, host: backend.internal, port: 8080, upstream_host: service}"),
+            Some("backend.internal:8080".to_string())
+        );
+        assert_eq!(
+            host("{name: app, host: backend.internal, port: 443, scheme: https, tls: {}, upstream_host: service}"),
+            Some("backend.internal".to_string())
+        );
+        assert_eq!(
+            host("{name: app, host: backend.internal, port: 80, scheme: https, tls: {}, upstream_host: service}"),
+            Some("backend.internal:80".to_string())
+        );
+    }
+
+    #[test]
+    fn should_not_send_sni_to_ip_literals() {
+        let upstream = upstream(
+            "{name: app, host: 10.0.0.5, port: 8443, scheme: https, tls: {}, upstream_host: '[::1]:8443'}",
+        );
+        let tls = upstream.tls.as_ref().unwrap();
+
+        let server_name = upstream.server_name(Some("[::1]:8443"));
+        assert_eq!(server_name.as_deref(), Some("::1"));
+        assert!(matches!(
+            tls.server_name(server_name.as_deref().unwrap()),
+            Ok(ServerName::IpAddress(_))
+        ));
+        // Connections without a host to follow fall back to the address connected to
+        assert!(matches!(
+            tls.server_name(&upstream.host),
+            Ok(ServerName::IpAddress(_))
+        ));
+        assert!(matches!(
+            tls.server_name("backend.internal"),
+            Ok(ServerName::DnsName(_))
+        ));
+    }
+}
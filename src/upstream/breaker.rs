@@ -0,0 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::env::state::CircuitBreakerConfig;
+use crate::metrics::Metrics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn gauge_value(self) -> i64 {
+        match self {
+            BreakerState::Closed => 0,
+            BreakerState::Open => 1,
+            BreakerState::HalfOpen => 2,
+        }
+    }
+}
+
+struct Breaker {
+    state: BreakerState,
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    probe_started_at: Option<Instant>,
+    window_started_at: Instant,
+    window_requests: u32,
+    window_failures: u32,
+}
+
+impl Breaker {
+    fn new() -> Self {
+        Self {
+            state: BreakerState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_started_at: None,
+            window_started_at: Instant::now(),
+            window_requests: 0,
+            window_failures: 0,
+        }
+    }
+
+    fn should_open(&self, config: &CircuitBreakerConfig) -> bool {
+        if config.consecutive_failures > 0
+            && self.consecutive_failures >= config.consecutive_failures
+        {
+            return true;
+        }
+
+        config.error_rate_percent.is_some_and(|threshold| {
+            self.window_requests >= config.min_requests.max(1)
+                && f64::from(self.window_failures) * 100.0 / f64::from(self.window_requests)
+                    >= threshold
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BreakerStatus {
+    pub service: String,
+    pub address: String,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    /// Seconds since the breaker opened, while it is not closed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open_for_seconds: Option<u64>,
+}
+
+/// Passive outlier detection: one breaker per service endpoint, fed with the outcome of every
+/// proxied request.
+pub struct CircuitBreakers {
+    breakers: Mutex<HashMap<(String, String), Breaker>>,
+    metrics: Arc<Metrics>,
+}
+
+impl CircuitBreakers {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            breakers: Mutex::new(HashMap::new()),
+            metrics,
+        }
+    }
+
+    /// Whether a request may be sent to the endpoint. After the cooldown an open breaker turns
+    /// half-open and lets a single probe through.
+    pub fn allow(&self, service: &str, address: &str, config: &CircuitBreakerConfig) -> bool {
+        let mut breakers = self.breakers.lock().unwrap();
+        let Some(breaker) = breakers.get_mut(&(service.to_string(), address.to_string())) else {
+            return true;
+        };
+        let cooldown = Duration::from_secs(config.cooldown_seconds);
+
+        match breaker.state {
+            BreakerState::Closed => true,
+            BreakerState::Open => {
+                if breaker.opened_at.is_some_and(|at| at.elapsed() < cooldown) {
+                    return false;
+                }
+                breaker.state = BreakerState::HalfOpen;
+                breaker.probe_started_at = Some(Instant::now());
+                self.set_gauge(service, address, BreakerState::HalfOpen);
+                log::info!(
+                    "Circuit breaker for {} ({}) is half-open, sending a probe",
+                    address,
+                    service
+                );
+                true
+            }
+            BreakerState::HalfOpen => {
+                // A probe that never reported back must not keep the endpoint locked forever
+                let stale = breaker
+                    .probe_started_at
+                    .is_none_or(|at| at.elapsed() >= cooldown);
+                if stale {
+                    breaker.probe_started_at = Some(Instant::now());
+                }
+                stale
+            }
+        }
+    }
+
+    pub fn record(
+        &self,
+        service: &str,
+        address: &str,
+        config: &CircuitBreakerConfig,
+        success: bool,
+    ) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers
+            .entry((service.to_string(), address.to_string()))
+            .or_insert_with(Breaker::new);
+
+        if breaker.window_started_at.elapsed() >= Duration::from_secs(config.window_seconds) {
+            breaker.window_started_at = Instant::now();
+            breaker.window_requests = 0;
+            breaker.window_failures = 0;
+        }
+        breaker.window_requests += 1;
+
+        if success {
+            breaker.consecutive_failures = 0;
+            if breaker.state != BreakerState::Closed {
+                breaker.state = BreakerState::Closed;
+                breaker.opened_at = None;
+                breaker.probe_started_at = None;
+                breaker.window_started_at = Instant::now();
+                breaker.window_requests = 0;
+                breaker.window_failures = 0;
+                self.set_gauge(service, address, BreakerState::Closed);
+                log::info!("Circuit breaker for {} ({}) closed", address, service);
+            }
+            return;
+        }
+
+        breaker.consecutive_failures += 1;
+        breaker.window_failures += 1;
+
+        let reopen = breaker.state == BreakerState::HalfOpen;
+        if reopen || (breaker.state == BreakerState::Closed && breaker.should_open(config)) {
+            breaker.state = BreakerState::Open;
+            breaker.opened_at = Some(Instant::now());
+            breaker.probe_started_at = None;
+            self.set_gauge(service, address, BreakerState::Open);
+            self.metrics
+                .circuit_breaker_opened_total
+                .with_label_values(&[service, address])
+                .inc();
+            log::warn!(
+                "Circuit breaker for {} ({}) opened after {} consecutive failures ({} of {} requests failed in the window)",
+                address,
+                service,
+                breaker.consecutive_failures,
+                breaker.window_failures,
+                breaker.window_requests
+            );
+        }
+    }
+
+    pub fn status(&self) -> Vec<BreakerStatus> {
+        let breakers = self.breakers.lock().unwrap();
+        let mut status: Vec<BreakerStatus> = breakers
+            .iter()
+            .map(|((service, address), breaker)| BreakerStatus {
+                service: service.clone(),
+                address: address.clone(),
+                state: breaker.state,
+                consecutive_failures: breaker.consecutive_failures,
+                open_for_seconds: breaker.opened_at.map(|at| at.elapsed().as_secs()),
+            })
+            .collect();
+        status.sort_by(|a, b| (&a.service, &a.address).cmp(&(&b.service, &b.address)));
+        status
+    }
+
+    /// Forgets the breakers of endpoints that are not in `current` anymore, after a port switch
+    /// or a config reload.
+    pub fn retain(&self, current: &HashSet<(String, String)>) {
+        let mut breakers = self.breakers.lock().unwrap();
+        breakers.retain(|key, _| {
+            let keep = current.contains(key);
+            if !keep {
+                let (service, address) = key;
+                let _ = self
+                    .metrics
+                    .circuit_breaker_state
+                    .remove_label_values(&[service, address]);
+            }
+            keep
+        });
+    }
+
+    fn set_gauge(&self, service: &str, address: &str, state: BreakerState) {
+        self.metrics
+            .circuit_breaker_state
+            .with_label_values(&[service, address])
+            .set(state.gauge_value());
+    }
+}
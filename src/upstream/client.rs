@@ -0,0 +1,154 @@
+use std::{future::Future, pin::Pin};
+
+use axum::{
+    body::Body,
+    http::{
+        header::{CONNECTION, HOST, TE, TRANSFER_ENCODING, UPGRADE},
+        uri::Uri,
+        HeaderMap, HeaderName, HeaderValue, Request, Response, Version,
+    },
+};
+use hyper::{
+    body::Incoming,
+    client::conn::{http1, http2, TrySendError},
+    upgrade::OnUpgrade,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+
+use super::{Upstream, UpstreamIo};
+use crate::env::state::Protocol;
+
+/// Drives an upstream connection; it has to be polled for requests to make progress.
+pub type Connection = Pin<Box<dyn Future<Output = hyper::Result<()>> + Send>>;
+
+/// Sends requests on an HTTP/1 or HTTP/2 upstream connection.
+pub enum Sender {
+    Http1(http1::SendRequest<Body>),
+    Http2(http2::SendRequest<Body>),
+}
+
+impl Sender {
+    /// Performs the handshake for the protocol of `upstream` over `io`.
+    pub async fn handshake(
+        upstream: &Upstream,
+        io: Box<dyn UpstreamIo>,
+    ) -> hyper::Result<(Self, Connection)> {
+        let io = TokioIo::new(io);
+
+        match upstream.protocol {
+            Protocol::Http1 => {
+                let (sender, conn) = http1::Builder::new()
+                    .preserve_header_case(true)
+                    .title_case_headers(true)
+                    .handshake(io)
+                    .await?;
+                Ok((Sender::Http1(sender), Box::pin(conn.with_upgrades())))
+            }
+            Protocol::H2c | Protocol::H2 => {
+                let (sender, conn) = http2::Builder::new(TokioExecutor::new())
+                    .handshake(io)
+                    .await?;
+                Ok((Sender::Http2(sender), Box::pin(conn)))
+            }
+        }
+    }
+
+    /// Points the request at the upstream: HTTP/1 takes the path in origin form, HTTP/2 an
+    /// absolute URI whose authority becomes `:authority`, replacing the `Host` header.
+    ///
+    /// Upgrade requests (e.g. WebSocket) from an HTTP/1.1 client to an HTTP/1 upstream keep their
+    /// `Upgrade` header, and the client's side of the upgrade is returned to be bridged with the
+    /// upstream's once it switches protocols.
+    pub fn prepare(
+        &self,
+        upstream: &Upstream,
+        req: &mut Request<Body>,
+    ) -> Result<Option<OnUpgrade>, String> {
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/")
+            .to_string();
+
+        let upgrade = matches!(self, Sender::Http1(_)) && is_upgrade(req.version(), req.headers());
+        remove_connection_headers(req.headers_mut(), true, upgrade);
+
+        let uri = match self {
+            Sender::Http1(_) => {
+                // Requests received over HTTP/2 or HTTP/3 go out as HTTP/1.1
+                if req.version() > Version::HTTP_11 {
+                    *req.version_mut() = Version::HTTP_11;
+                }
+                path_and_query
+            }
+            Sender::Http2(_) => {
+                let authority = req
+                    .headers_mut()
+                    .remove(HOST)
+                    .and_then(|host| host.to_str().ok().map(String::from))
+                    .unwrap_or_else(|| upstream.address());
+                format!("{}://{}{}", upstream.scheme(), authority, path_and_query)
+            }
+        };
+
+        *req.uri_mut() = uri.parse::<Uri>().map_err(|e| e.to_string())?;
+        Ok(upgrade.then(|| hyper::upgrade::on(req)))
+    }
+
+    pub async fn try_send_request(
+        &mut self,
+        req: Request<Body>,
+    ) -> Result<Response<Incoming>, TrySendError<Request<Body>>> {
+        match self {
+            Sender::Http1(sender) => sender.try_send_request(req).await,
+            Sender::Http2(sender) => sender.try_send_request(req).await,
+        }
+    }
+}
+
+/// Whether an HTTP/1.1 message asks to switch protocols, with `Upgrade` listed in `Connection`.
+pub fn is_upgrade(version: Version, headers: &HeaderMap) -> bool {
+    version == Version::HTTP_11
+        && headers.contains_key(UPGRADE)
+        && headers
+            .get_all(CONNECTION)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|name| name.trim().eq_ignore_ascii_case("upgrade"))
+}
+
+/// Removes the headers that only apply to one connection (RFC 9110, section 7.6.1), so they are
+/// neither forwarded nor carried across HTTP versions. Requests keep `TE: trailers`, which gRPC
+/// relies on, and with `keep_upgrade` the `Upgrade` header is passed on with `Connection: upgrade`.
+pub fn remove_connection_headers(headers: &mut HeaderMap, is_request: bool, keep_upgrade: bool) {
+    let upgrade = headers.get(UPGRADE).cloned().filter(|_| keep_upgrade);
+    let named: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+    for name in named {
+        headers.remove(name);
+    }
+
+    for name in [CONNECTION, TRANSFER_ENCODING, UPGRADE] {
+        headers.remove(name);
+    }
+    for name in ["keep-alive", "proxy-connection"] {
+        headers.remove(name);
+    }
+
+    let keep_te = is_request && headers.get(TE).is_some_and(|te| te == "trailers");
+    if !keep_te {
+        headers.remove(TE);
+    }
+
+    if let Some(upgrade) = upgrade {
+        headers.insert(UPGRADE, upgrade);
+        headers.insert(CONNECTION, HeaderValue::from_static("upgrade"));
+    }
+}
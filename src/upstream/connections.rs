@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::body::{Bytes, HttpBody};
+use hyper::body::{Frame, SizeHint};
+use serde::Serialize;
+use tokio::{sync::Notify, time::Instant};
+
+use crate::metrics::Metrics;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectionCounts {
+    pub requests: usize,
+    pub connections: usize,
+}
+
+impl ConnectionCounts {
+    fn is_idle(&self) -> bool {
+        self.requests == 0 && self.connections == 0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Request,
+    Connection,
+}
+
+/// Requests and connections currently open to each upstream address, so an old port can be
+/// drained before its process is stopped.
+pub struct ActiveConnections {
+    counts: Mutex<HashMap<String, ConnectionCounts>>,
+    changed: Notify,
+    metrics: Arc<Metrics>,
+}
+
+impl ActiveConnections {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self {
+            counts: Mutex::new(HashMap::new()),
+            changed: Notify::new(),
+            metrics,
+        }
+    }
+
+    pub fn request(self: &Arc<Self>, service: &str, address: &str) -> ActiveGuard {
+        self.track(service, address, Kind::Request)
+    }
+
+    pub fn connection(self: &Arc<Self>, service: &str, address: &str) -> ActiveGuard {
+        self.track(service, address, Kind::Connection)
+    }
+
+    /// Totals over `addresses`.
+    pub fn counts(&self, addresses: &[String]) -> ConnectionCounts {
+        let counts = self.counts.lock().unwrap();
+
+        addresses
+            .iter()
+            .filter_map(|address| counts.get(address))
+            .fold(ConnectionCounts::default(), |total, c| ConnectionCounts {
+                requests: total.requests + c.requests,
+                connections: total.connections + c.connections,
+            })
+    }
+
+    /// Waits until nothing is open to `addresses`, returning `false` if `timeout` ran out first.
+    pub async fn wait_drained(&self, addresses: &[String], timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.counts(addresses).is_idle() {
+                return true;
+            }
+
+            tokio::select! {
+                _ = changed => {}
+                _ = tokio::time::sleep_until(deadline) => return false,
+            }
+        }
+    }
+
+    fn track(self: &Arc<Self>, service: &str, address: &str, kind: Kind) -> ActiveGuard {
+        self.update(service, address, kind, |count| *count += 1);
+
+        ActiveGuard {
+            active: self.clone(),
+            service: service.to_string(),
+            address: address.to_string(),
+            kind,
+        }
+    }
+
+    fn update(&self, service: &str, address: &str, kind: Kind, change: impl Fn(&mut usize)) {
+        let mut counts = self.counts.lock().unwrap();
+        let entry = counts.entry(address.to_string()).or_default();
+
+        let (count, gauge) = match kind {
+            Kind::Request => (&mut entry.requests, &self.metrics.upstream_active_requests),
+            Kind::Connection => (
+                &mut entry.connections,
+                &self.metrics.upstream_active_connections,
+            ),
+        };
+        change(count);
+        gauge
+            .with_label_values(&[service, address])
+            .set(*count as i64);
+
+        if entry.is_idle() {
+            counts.remove(address);
+        }
+        self.changed.notify_waiters();
+    }
+}
+
+/// Counts a request or connection as active until dropped.
+pub struct ActiveGuard {
+    active: Arc<ActiveConnections>,
+    service: String,
+    address: String,
+    kind: Kind,
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.active
+            .update(&self.service, &self.address, self.kind, |count| {
+                *count = count.saturating_sub(1)
+            });
+    }
+}
+
+/// Response body that keeps its request counted as active until it is fully sent or dropped.
+pub struct TrackedBody<B> {
+    inner: B,
+    _guard: ActiveGuard,
+}
+
+impl<B> TrackedBody<B> {
+    pub fn new(inner: B, guard: ActiveGuard) -> Self {
+        Self {
+            inner,
+            _guard: guard,
+        }
+    }
+}
+
+impl<B> HttpBody for TrackedBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Pin::new(&mut self.get_mut().inner).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
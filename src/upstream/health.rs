@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use axum::body::Body;
+use http_body_util::BodyExt;
+use hyper::{header, Request, StatusCode};
+
+use super::{
+    client::Sender,
+    timeout::{with_timeout, TimeoutKind},
+    Upstream,
+};
+use crate::env::state::{HealthCheckConfig, HealthCheckMode, Protocol};
+
+const GRPC_HEALTH_PATH: &str = "/grpc.health.v1.Health/Check";
+/// `ServingStatus.SERVING` in `grpc.health.v1`
+const GRPC_SERVING: u64 = 1;
+
+/// Sends a single health check request to the upstream, over TLS when the service uses
+/// `https`: a `GET` for the configured path, or a gRPC health check. Gives up after the
+/// configured timeout, so a silent upstream can't hold up a port switch.
+pub async fn check(upstream: &Upstream, config: &HealthCheckConfig) -> Result<StatusCode, String> {
+    let limit = (config.timeout_seconds > 0).then(|| Duration::from_secs(config.timeout_seconds));
+
+    with_timeout(TimeoutKind::Total, limit, send_check(upstream, config))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+async fn send_check(upstream: &Upstream, config: &HealthCheckConfig) -> Result<StatusCode, String> {
+    if config.mode == HealthCheckMode::Grpc && upstream.protocol == Protocol::Http1 {
+        return Err("gRPC health checks need protocol h2c or h2".to_string());
+    }
+
+    let host = upstream
+        .request_host(None)
+        .unwrap_or_else(|| upstream.address());
+    let io = upstream
+        .connect(upstream.server_name(Some(&host)).as_deref(), None)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let (mut sender, conn) = Sender::handshake(upstream, io)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    tokio::spawn(async move {
+        if let Err(err) = conn.await {
+            log::debug!("Health check connection error: {}", err);
+        }
+    });
+
+    let mut request = match config.mode {
+        HealthCheckMode::Http => Request::get(config.path.as_str())
+            .header(header::HOST, host)
+            .body(Body::empty()),
+        HealthCheckMode::Grpc => Request::post(GRPC_HEALTH_PATH)
+            .header(header::HOST, host)
+            .header(header::CONTENT_TYPE, "application/grpc")
+            .header(header::TE, "trailers")
+            .body(Body::from(grpc_request(&config.grpc_service))),
+    }
+    .map_err(|e| e.to_string())?;
+    sender.prepare(upstream, &mut request)?;
+
+    let response = sender
+        .try_send_request(request)
+        .await
+        .map_err(|e| e.into_error().to_string())?;
+
+    match config.mode {
+        HealthCheckMode::Http => Ok(response.status()),
+        HealthCheckMode::Grpc => {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response
+                .into_body()
+                .collect()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            // A failing call may carry its status in the headers alone ("trailers-only")
+            let grpc_status = body
+                .trailers()
+                .and_then(|trailers| trailers.get("grpc-status"))
+                .or_else(|| headers.get("grpc-status"))
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or("")
+                .to_string();
+            if status != StatusCode::OK || grpc_status != "0" {
+                return Err(format!(
+                    "gRPC health check failed with HTTP {} and grpc-status '{}'",
+                    status, grpc_status
+                ));
+            }
+
+            match grpc_serving_status(&body.to_bytes()) {
+                Some(GRPC_SERVING) => Ok(status),
+                serving => Err(format!("gRPC service is not serving ({:?})", serving)),
+            }
+        }
+    }
+}
+
+/// A length-prefixed `HealthCheckRequest { service }` message.
+pub fn grpc_request(service: &str) -> Vec<u8> {
+    let mut message = Vec::new();
+    if !service.is_empty() {
+        message.push(0x0a); // field 1, length delimited
+        put_varint(&mut message, service.len() as u64);
+        message.extend_from_slice(service.as_bytes());
+    }
+
+    let mut frame = vec![0]; // not compressed
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    frame
+}
+
+/// Reads `status` (field 1) from a length-prefixed `HealthCheckResponse`.
+pub fn grpc_serving_status(frame: &[u8]) -> Option<u64> {
+    let mut message = frame.get(5..)?;
+
+    while !message.is_empty() {
+        let key = take_varint(&mut message)?;
+        match (key >> 3, key & 7) {
+            (1, 0) => return take_varint(&mut message),
+            (_, 0) => {
+                take_varint(&mut message)?;
+            }
+            (_, 2) => {
+                let len = usize::try_from(take_varint(&mut message)?).ok()?;
+                message = message.get(len..)?;
+            }
+            _ => return None,
+        }
+    }
+
+    // Proto3 leaves out default values, and the default status is UNKNOWN
+    Some(0)
+}
+
+pub fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+pub fn take_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = buf.split_first()?;
+        *buf = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
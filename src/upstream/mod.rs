@@ -0,0 +1,168 @@
+mod __tests__;
+
+use std::{fmt, io, sync::Arc};
+
+use hyper::http::uri::Authority;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_rustls::TlsConnector;
+
+use self::{
+    timeout::{with_timeout, TimeoutKind, Timeouts},
+    tls::UpstreamTls,
+};
+use crate::{
+    env::state::{Protocol, ProxyProtocolVersion, Service, UpstreamHost},
+    proxy_protocol::{self, Addresses},
+};
+
+pub mod breaker;
+pub mod client;
+pub mod connections;
+pub mod health;
+pub mod retry;
+pub mod timeout;
+pub mod tls;
+
+pub trait UpstreamIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> UpstreamIo for T {}
+
+/// A single backend address, together with the TLS settings of the service it belongs to.
+#[derive(Clone)]
+pub struct Upstream {
+    pub service: String,
+    pub host: String,
+    pub port: u16,
+    pub tls: Option<Arc<UpstreamTls>>,
+    pub protocol: Protocol,
+    pub timeouts: Timeouts,
+    pub upstream_host: Option<UpstreamHost>,
+    pub proxy_protocol: Option<ProxyProtocolVersion>,
+}
+
+impl Upstream {
+    pub fn new(
+        service: &Service,
+        host: &str,
+        port: u16,
+        tls: Option<Arc<UpstreamTls>>,
+        timeouts: Timeouts,
+    ) -> Self {
+        Self {
+            service: service.name.clone(),
+            host: host.to_string(),
+            port,
+            tls,
+            protocol: service.protocol,
+            timeouts,
+            upstream_host: service.upstream_host.clone(),
+            proxy_protocol: service.proxy_protocol,
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    /// Host header for a request whose client asked for `client_host`.
+    pub fn request_host(&self, client_host: Option<&str>) -> Option<String> {
+        match &self.upstream_host {
+            None | Some(UpstreamHost::Preserve) => client_host.map(String::from),
+            Some(UpstreamHost::Service) => {
+                let default_port = if self.tls.is_some() { 443 } else { 80 };
+                Some(if self.port == default_port {
+                    self.host.clone()
+                } else {
+                    self.address()
+                })
+            }
+            Some(UpstreamHost::Literal(host)) => Some(host.clone()),
+        }
+    }
+
+    /// TLS server name for a request sent with the `host` header, which it follows only when
+    /// `upstream_host` is configured.
+    pub fn server_name(&self, host: Option<&str>) -> Option<String> {
+        self.upstream_host.as_ref()?;
+
+        let authority = host?.parse::<Authority>().ok()?;
+        Some(authority.host().trim_matches(['[', ']']).to_string())
+    }
+
+    /// Opens a connection, giving up with `TimedOut` after the connect timeout. `server_name`
+    /// replaces the host as the default TLS server name, and `client` is announced with the
+    /// PROXY protocol when the service asks for it.
+    pub async fn connect(
+        &self,
+        server_name: Option<&str>,
+        client: Option<&Addresses>,
+    ) -> io::Result<Box<dyn UpstreamIo>> {
+        with_timeout(
+            TimeoutKind::Connect,
+            self.timeouts.connect,
+            self.open(server_name, client),
+        )
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
+    }
+
+    /// Opens a plain TCP connection, without the TLS of the service, for streams that carry their
+    /// own.
+    pub async fn connect_tcp(&self, client: Option<&Addresses>) -> io::Result<TcpStream> {
+        with_timeout(
+            TimeoutKind::Connect,
+            self.timeouts.connect,
+            self.open_tcp(client),
+        )
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::TimedOut, e))?
+    }
+
+    async fn open(
+        &self,
+        server_name: Option<&str>,
+        client: Option<&Addresses>,
+    ) -> io::Result<Box<dyn UpstreamIo>> {
+        let stream = self.open_tcp(client).await?;
+
+        let Some(tls) = &self.tls else {
+            return Ok(Box::new(stream));
+        };
+
+        let server_name = tls.server_name(server_name.unwrap_or(&self.host))?;
+        let stream = TlsConnector::from(tls.config.clone())
+            .connect(server_name, stream)
+            .await?;
+
+        Ok(Box::new(stream))
+    }
+
+    async fn open_tcp(&self, client: Option<&Addresses>) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+
+        if let Some(version) = self.proxy_protocol {
+            stream
+                .write_all(&proxy_protocol::encode(version, client))
+                .await?;
+        }
+
+        Ok(stream)
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}:{}", self.scheme(), self.host, self.port)
+    }
+}
@@ -0,0 +1,107 @@
+use std::{sync::Mutex, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{HeaderMap, Method, Uri, Version},
+};
+
+use crate::env::state::RetryConfig;
+use crate::proxy_protocol::Addresses;
+
+/// Token bucket limiting retries to `budget_ratio` of the requests plus a burst of
+/// `budget_burst`, so retries can't multiply the load on a failing service.
+pub struct RetryBudget {
+    /// What the budget was built from, to keep it across reloads that don't change it
+    config: RetryConfig,
+    ratio: f64,
+    burst: f64,
+    tokens: Mutex<f64>,
+}
+
+impl RetryBudget {
+    pub fn new(config: &RetryConfig) -> Self {
+        let burst = f64::from(config.budget_burst);
+
+        Self {
+            config: config.clone(),
+            ratio: config.budget_ratio.max(0.0),
+            burst,
+            tokens: Mutex::new(burst),
+        }
+    }
+
+    pub fn is_built_from(&self, config: &RetryConfig) -> bool {
+        self.config == *config
+    }
+
+    /// Called once per original request.
+    pub fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + self.ratio).min(self.burst);
+    }
+
+    pub fn try_withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+/// Exponential backoff with full jitter before retry number `retry` (starting at 1).
+pub fn backoff(config: &RetryConfig, retry: u32) -> Duration {
+    let ceiling = config
+        .backoff_base_ms
+        .saturating_mul(1 << retry.saturating_sub(1).min(16))
+        .min(config.backoff_max_ms);
+
+    if ceiling == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(fastrand::u64(0..=ceiling))
+}
+
+/// Head of a bodiless request, kept to send it again after a retryable response.
+pub struct RequestTemplate {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    /// Announced again with the PROXY protocol
+    client: Option<Addresses>,
+}
+
+impl RequestTemplate {
+    pub fn new(req: &Request) -> Self {
+        Self {
+            method: req.method().clone(),
+            uri: req.uri().clone(),
+            version: req.version(),
+            headers: req.headers().clone(),
+            client: req.extensions().get::<Addresses>().copied(),
+        }
+    }
+
+    pub fn build(&self) -> Request {
+        let mut req = Request::new(Body::empty());
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+        if let Some(client) = self.client {
+            req.extensions_mut().insert(client);
+        }
+        req
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum::body::{Bytes, HttpBody};
+use hyper::body::{Frame, SizeHint};
+use tokio::time::{sleep, sleep_until, Instant, Sleep};
+
+use crate::env::state::TimeoutConfig;
+
+const DEFAULT_CONNECT: Duration = Duration::from_secs(5);
+
+/// Timeouts of a service after applying its overrides on top of the global settings.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub idle: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn resolve(global: &TimeoutConfig, service: Option<&TimeoutConfig>) -> Self {
+        let pick =
+            |field: fn(&TimeoutConfig) -> Option<u64>, default: Option<Duration>| match service
+                .and_then(field)
+                .or_else(|| field(global))
+            {
+                Some(0) => None,
+                Some(ms) => Some(Duration::from_millis(ms)),
+                None => default,
+            };
+
+        Self {
+            connect: pick(|c| c.connect_ms, Some(DEFAULT_CONNECT)),
+            // Unset by default, long polling and event streams may stay silent for a long time
+            first_byte: pick(|c| c.first_byte_ms, None),
+            idle: pick(|c| c.idle_ms, None),
+            total: pick(|c| c.total_ms, None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutKind {
+    Connect,
+    FirstByte,
+    Idle,
+    Total,
+}
+
+impl fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutKind::Connect => "connect",
+            TimeoutKind::FirstByte => "first byte",
+            TimeoutKind::Idle => "body idle",
+            TimeoutKind::Total => "total",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub kind: TimeoutKind,
+    pub after: Duration,
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "upstream {} timeout after {:?}", self.kind, self.after)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// Runs `future`, failing with `kind` once `limit` elapses. `None` waits forever.
+pub async fn with_timeout<F: Future>(
+    kind: TimeoutKind,
+    limit: Option<Duration>,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    match limit {
+        Some(after) => tokio::time::timeout(after, future)
+            .await
+            .map_err(|_| TimeoutError { kind, after }),
+        None => Ok(future.await),
+    }
+}
+
+/// Runs `future` until `deadline`, a point in time shared by several attempts, reporting the
+/// `total` it was derived from when it passes. `None` waits forever.
+pub async fn with_deadline<F: Future>(
+    kind: TimeoutKind,
+    deadline: Option<(Duration, Instant)>,
+    future: F,
+) -> Result<F::Output, TimeoutError> {
+    match deadline {
+        Some((total, at)) => tokio::time::timeout_at(at, future)
+            .await
+            .map_err(|_| TimeoutError { kind, after: total }),
+        None => Ok(future.await),
+    }
+}
+
+/// Response body that fails when the upstream stalls between chunks or misses the overall
+/// deadline. The failure aborts the client connection, since the status is already sent.
+pub struct TimeoutBody<B> {
+    inner: B,
+    upstream: String,
+    idle: Option<(Duration, Pin<Box<Sleep>>)>,
+    deadline: Option<(Duration, Pin<Box<Sleep>>)>,
+}
+
+impl<B> TimeoutBody<B> {
+    pub fn new(inner: B, upstream: String, timeouts: &Timeouts, deadline: Option<Instant>) -> Self {
+        Self {
+            inner,
+            upstream,
+            idle: timeouts.idle.map(|idle| (idle, Box::pin(sleep(idle)))),
+            deadline: timeouts
+                .total
+                .zip(deadline)
+                .map(|(total, at)| (total, Box::pin(sleep_until(at)))),
+        }
+    }
+
+    fn fail(&self, kind: TimeoutKind, after: Duration) -> TimeoutError {
+        tracing::error!(
+            "Upstream {} timeout ({:?}) fired while streaming the response from {}",
+            kind,
+            after,
+            self.upstream
+        );
+        TimeoutError { kind, after }
+    }
+}
+
+impl<B> HttpBody for TimeoutBody<B>
+where
+    B: HttpBody<Data = Bytes> + Unpin,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Data = Bytes;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+
+        if let Poll::Ready(frame) = Pin::new(&mut this.inner).poll_frame(cx) {
+            if let Some((idle, sleep)) = &mut this.idle {
+                sleep.as_mut().reset(Instant::now() + *idle);
+            }
+            return Poll::Ready(frame.map(|frame| frame.map_err(Into::into)));
+        }
+
+        if let Some((total, sleep)) = &mut this.deadline {
+            if sleep.as_mut().poll(cx).is_ready() {
+                let total = *total;
+                return Poll::Ready(Some(Err(this.fail(TimeoutKind::Total, total).into())));
+            }
+        }
+
+        if let Some((idle, sleep)) = &mut this.idle {
+            if sleep.as_mut().poll(cx).is_ready() {
+                let idle = *idle;
+                return Poll::Ready(Some(Err(this.fail(TimeoutKind::Idle, idle).into())));
+            }
+        }
+
+        Poll::Pending
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
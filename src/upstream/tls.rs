@@ -0,0 +1,128 @@
+use std::{error::Error, io, sync::Arc};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::{
+    env::state::{Protocol, UpstreamTlsConfig},
+    utils::pem::{load_certs, load_private_key},
+};
+
+/// Prepared TLS client settings for an `https` service.
+#[derive(Debug)]
+pub struct UpstreamTls {
+    pub config: Arc<ClientConfig>,
+    pub server_name: Option<String>,
+}
+
+impl UpstreamTls {
+    pub fn from_config(
+        config: &UpstreamTlsConfig,
+        protocol: Protocol,
+    ) -> Result<Self, Box<dyn Error>> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = if config.insecure_skip_verify {
+            log::warn!("TLS certificate verification is disabled for an upstream service");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+        } else {
+            builder.with_root_certificates(root_store(config.ca_file.as_deref())?)
+        };
+
+        let mut client_config = match (&config.client_cert, &config.client_key) {
+            (Some(cert), Some(key)) => {
+                builder.with_client_auth_cert(load_certs(cert)?, load_private_key(key)?)?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => return Err("client_cert and client_key must be set together".into()),
+        };
+        if protocol == Protocol::H2 {
+            client_config.alpn_protocols = vec![b"h2".to_vec()];
+        }
+
+        Ok(Self {
+            config: Arc::new(client_config),
+            server_name: config.server_name.clone(),
+        })
+    }
+
+    /// SNI sent to the upstream: the configured override, or the host being connected to.
+    pub fn server_name(&self, host: &str) -> io::Result<ServerName<'static>> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+
+        ServerName::try_from(name.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+fn root_store(ca_file: Option<&str>) -> Result<RootCertStore, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+
+    match ca_file {
+        Some(path) => {
+            for cert in load_certs(path)? {
+                roots.add(cert)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+
+    Ok(roots)
+}
+
+/// Accepts any server certificate. Only meant for development setups with self-signed certs.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
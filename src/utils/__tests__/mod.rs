@@ -0,0 +1,2 @@
+mod pattern;
+mod request_id;
@@ -0,0 +1,21 @@
+#[cfg(test)]
+mod tests {
+    use crate::utils::pattern::wildcard_match;
+
+    #[test]
+    fn should_match_literal_and_wildcards() {
+        assert!(wildcard_match("CN=admin", "CN=admin"));
+        assert!(wildcard_match("CN=svc-*", "CN=svc-billing"));
+        assert!(wildcard_match("spiffe://corp/*", "spiffe://corp/billing"));
+        assert!(wildcard_match("*.internal", "billing.internal"));
+        assert!(wildcard_match("*a*b*", "xxaxxbxx"));
+        assert!(wildcard_match("*", ""));
+    }
+
+    #[test]
+    fn should_reject_non_matching_values() {
+        assert!(!wildcard_match("CN=admin", "CN=administrator"));
+        assert!(!wildcard_match("*.internal", "billing.internal.evil"));
+        assert!(!wildcard_match("svc-*-prod", "svc-billing-dev"));
+    }
+}
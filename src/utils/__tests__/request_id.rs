@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, Request},
+        middleware::from_fn_with_state,
+        Extension, Router,
+    };
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use crate::env::state::RequestIdConfig;
+    use crate::utils::request_id::{middleware, RequestId, RequestIds};
+
+    /// Answers with the ID seen by the handler, returning it with the `x-request-id` response
+    /// header.
+    async fn send(client: &str, incoming: Option<&str>) -> (String, String) {
+        let config: RequestIdConfig =
+            serde_yaml::from_str("trusted_clients: [10.0.0.0/8]").unwrap();
+        let request_ids = Arc::new(RequestIds::from_config(&config).unwrap());
+        let app = Router::new()
+            .fallback(
+                |Extension(RequestId(id)): Extension<RequestId>, req: Request| async move {
+                    assert_eq!(req.headers()["x-request-id"], id.as_str());
+                    id
+                },
+            )
+            .layer(from_fn_with_state(request_ids, middleware));
+
+        let mut req = Request::get("/");
+        if let Some(id) = incoming {
+            req = req.header("x-request-id", id);
+        }
+        let mut req = req.body(Body::empty()).unwrap();
+        req.extensions_mut()
+            .insert(ConnectInfo(client.parse::<SocketAddr>().unwrap()));
+
+        let response = app.oneshot(req).await.unwrap();
+        let header = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (String::from_utf8(body.to_vec()).unwrap(), header)
+    }
+
+    #[tokio::test]
+    async fn should_reuse_request_ids_of_trusted_clients() {
+        let (id, header) = send("10.1.2.3:5000", Some("edge-1234")).await;
+
+        assert_eq!(id, "edge-1234");
+        assert_eq!(header, "edge-1234");
+    }
+
+    #[tokio::test]
+    async fn should_replace_request_ids_of_untrusted_clients() {
+        let (id, header) = send("192.0.2.1:5000", Some("edge-1234")).await;
+
+        assert_ne!(id, "edge-1234");
+        assert_eq!(id, header);
+        assert!(uuid::Uuid::parse_str(&id).is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_replace_invalid_request_ids_of_trusted_clients() {
+        let too_long = "a".repeat(129);
+        for incoming in [Some(""), Some("two words"), Some(too_long.as_str()), None] {
+            let (id, header) = send("10.1.2.3:5000", incoming).await;
+
+            assert_eq!(id, header);
+            assert!(uuid::Uuid::parse_str(&id).is_ok(), "kept {:?}", incoming);
+        }
+    }
+}
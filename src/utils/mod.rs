@@ -1 +1,7 @@
+mod __tests__;
+
 pub mod log;
+pub mod pattern;
+pub mod pem;
+pub mod request_id;
+pub mod template;
@@ -0,0 +1,28 @@
+use std::{fs::File, io, io::BufReader};
+
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+pub fn load_certs(path: &str) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+
+    if certs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No certificates found in {}", path),
+        ));
+    }
+
+    Ok(certs)
+}
+
+pub fn load_private_key(path: &str) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    rustls_pemfile::private_key(&mut reader)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("No private key found in {}", path),
+        )
+    })
+}
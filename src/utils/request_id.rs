@@ -0,0 +1,85 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use ipnet::IpNet;
+use tracing::Instrument;
+use uuid::Uuid;
+
+use crate::env::state::RequestIdConfig;
+use crate::telemetry;
+
+const MAX_INCOMING_LENGTH: usize = 128;
+
+/// ID of the request being proxied, shared with the upstream, the client and every log line.
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+pub struct RequestIds {
+    header: HeaderName,
+    trusted_clients: Vec<IpNet>,
+}
+
+impl RequestIds {
+    pub fn from_config(config: &RequestIdConfig) -> Result<Self, String> {
+        let header = HeaderName::try_from(config.header.as_str())
+            .map_err(|e| format!("Invalid request ID header '{}': {}", config.header, e))?;
+
+        Ok(Self {
+            header,
+            trusted_clients: config.trusted_clients.clone(),
+        })
+    }
+
+    fn is_trusted(&self, req: &Request) -> bool {
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .is_some_and(|ConnectInfo(addr)| {
+                self.trusted_clients
+                    .iter()
+                    .any(|network| network.contains(&addr.ip()))
+            })
+    }
+
+    fn incoming(&self, req: &Request) -> Option<String> {
+        let value = req.headers().get(&self.header)?.to_str().ok()?;
+
+        let is_valid = !value.is_empty()
+            && value.len() <= MAX_INCOMING_LENGTH
+            && value.bytes().all(|b| b.is_ascii_graphic());
+
+        is_valid.then(|| value.to_string())
+    }
+}
+
+/// Assigns a request ID (reusing the client's one when it is trusted), forwards it upstream,
+/// echoes it in the response and attaches it to the tracing span of the request.
+pub async fn middleware(
+    State(request_ids): State<Arc<RequestIds>>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let id = request_ids
+        .is_trusted(&req)
+        .then(|| request_ids.incoming(&req))
+        .flatten()
+        .unwrap_or_else(|| Uuid::now_v7().to_string());
+
+    let header_value = HeaderValue::from_str(&id).expect("request IDs are visible ASCII");
+    req.headers_mut()
+        .insert(request_ids.header.clone(), header_value.clone());
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let span = telemetry::request_span(&req, &id);
+    let mut response = next.run(req).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+
+    response
+        .headers_mut()
+        .insert(request_ids.header.clone(), header_value);
+    response
+}
@@ -0,0 +1,36 @@
+/// Expands `$name` and `${name}` variables in `template`.
+///
+/// Variables `lookup` does not know are left untouched.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+
+        let (name, consumed) = if let Some(braced) = after.strip_prefix('{') {
+            match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            }
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end)
+        };
+
+        let value = if name.is_empty() { None } else { lookup(name) };
+
+        match value {
+            Some(value) => output.push_str(&value),
+            None => output.push_str(&rest[start..start + 1 + consumed]),
+        }
+
+        rest = &after[consumed..];
+    }
+
+    output.push_str(rest);
+    output
+}